@@ -18,6 +18,8 @@ use opalite::{
     CollisionLayer,
     CollisionLayers,
     Data,
+    Easing,
+    Interpolation,
     Light,
     LightType,
     MaterialDesc,
@@ -45,7 +47,8 @@ fn main() {
         .add_dispatcher_end()
         .add_dispatcher_thread_local()
         .add_world()
-        .build();
+        .build()
+        .unwrap();
 
     /*opal.world_mut().create_entity()
         .with(InitialPosition((0, 1, 0).into()))
@@ -57,19 +60,7 @@ fn main() {
         })
         .build();*/
 
-    opal.world_mut().create_entity()
-        .with(InitialPosition((0, 1, 0).into()))
-        .with(CollisionLayers::new([CollisionLayer::PLAYER].iter()))
-        .with(ModelKey::new(ModelType::File("Suzanne/glTF/Suzanne.glb".into())))
-        .with(MaterialDesc {
-            diffuse: SurfaceType::Color(vec4(1.0, 1.0, 1.0, 1.0)),
-            specular: float(10.0),
-        })
-        .with(ModelData {
-            scale: Vector3::new(0.5, 0.5, 0.5),
-            .. Default::default()
-        })
-        .build();
+    opal.spawn_prefab("suzanne", Vector3::new(0, 1, 0)).unwrap();
 
     let entity = opal.world_mut().create_entity()
         .with(InitialPosition((0, 0, 0).into()))
@@ -85,6 +76,7 @@ fn main() {
                                 target: start,
                                 path: VecDeque::new(),
                             }],
+                            _ => vec![],
                         }
                     },
                     None => vec![
@@ -96,7 +88,8 @@ fn main() {
                     ]
                 }
             }),
-            Box::new(|_| panic!("AI Error"))))
+            Box::new(|_, failure| panic!("AI Error: {}", failure))))
+        .with(Interpolation::new(0.25, Easing::EaseInOut))
         .with(ModelKey::new(ModelType::Sphere))
         .with(MaterialDesc {
             diffuse: SurfaceType::Color(vec4(0.5, 0.5, 0.0, 1.0)),
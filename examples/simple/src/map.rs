@@ -6,6 +6,7 @@ use opalite::{
     specs::World,
     Backend as B,
     Buffer,
+    MapTopology,
     MaterialDesc,
     Model,
     ModelData,
@@ -153,14 +154,10 @@ impl HexGrid {
     }
 
     fn create_cell(x: i32, z: i32, _i: i32) -> HexCell {
-        let xf = x as f32;
-        let zf = z as f32;
-        let zf_2 = (z / 2) as f32;
+        // offset to axial coordinates, so the grid stays rectangular
+        let q = x - z / 2;
 
-        let xf = (xf + (zf * 0.5) - zf_2) * (HexMetrics::INNER * 2.0);
-        let zf = zf * (HexMetrics::OUTER * 1.5);
-
-        let position = Vector3::new(xf, 0.0, zf);
+        let position = MapTopology::Hex.to_world(&Vector3::new(q, 0, z));
         let center = Vertex {
             position,
             .. Default::default()
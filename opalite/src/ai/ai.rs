@@ -0,0 +1,447 @@
+use std::{ collections::VecDeque, sync::{ Mutex, mpsc } };
+use cgmath::Vector3;
+use specs::{ Entities, Entity, Fetch, FetchMut, ReadStorage, System, VecStorage, WriteStorage };
+use crate::{
+    CollisionLayers,
+    Data,
+    EventBus,
+    Map,
+    MapMessage,
+    Message,
+    MessageSender,
+    PathError,
+    Position,
+    RLock,
+    TurnScheduler,
+};
+use crate::opal::Gluon;
+use super::{ AiScript, BehaviourCommand, BehaviourContext, BehaviourStatus, BehaviourTree, ScriptCache };
+
+#[derive(Clone, Debug)]
+pub enum AiGoalDo {
+    Replace(Vec<AiGoal>),
+    Queue(Vec<AiGoal>),
+    Continue,
+}
+
+#[derive(Clone, Debug)]
+pub enum AiGoal {
+    Move { start: Vector3<i32>, target: Vector3<i32>, path: VecDeque<Vector3<i32>> },
+    // in ticks
+    Wait { remaining: u32 },
+    // complete once within `distance` of `target`
+    Follow { target: Entity, distance: i32, path: VecDeque<Vector3<i32>> },
+    // complete once at least `distance` away from `from`
+    Flee { from: Entity, distance: i32 },
+    // move next to `target` and publish `AiMessages::Interact`
+    Interact { target: Entity, path: VecDeque<Vector3<i32>> },
+}
+
+#[derive(Fail, Clone, Debug)]
+pub enum AiGoalFailure {
+    #[fail(display = "{}", _0)]
+    Path(#[cause] PathError),
+    #[fail(display = "Move to {:?} was rejected {} times.", _0, _1)]
+    MoveRejected(Vector3<i32>, u32),
+    #[fail(display = "Target {:?} isn't on the map.", _0)]
+    TargetLost(Entity),
+    #[fail(display = "Can't get further away from {:?}.", _0)]
+    Cornered(Entity),
+}
+
+// how many times in a row a move can be rejected before the goal fails
+const MAX_REJECTED_MOVES: u32 = 3;
+
+struct PendingMove {
+    location: Vector3<i32>,
+    reply: Mutex<mpsc::Receiver<bool>>,
+}
+
+impl PendingMove {
+    // `None` if `MapSystem` hasn't handled the move yet
+    fn result(&self) -> Option<bool> {
+        match self.reply.lock().unwrap().try_recv() {
+            Ok(accepted) => Some(accepted),
+            Err(mpsc::TryRecvError::Empty) => None,
+            // the message was dropped without a reply
+            Err(mpsc::TryRecvError::Disconnected) => Some(false),
+        }
+    }
+}
+
+#[derive(Component)]
+#[component(VecStorage)]
+pub struct AiComponent {
+    current_goal: Option<AiGoal>,
+    goals: VecDeque<AiGoal>,
+    pending_move: Option<PendingMove>,
+    rejected_moves: u32,
+    behaviour: Option<BehaviourTree>,
+
+    goal_do: Box<Fn(&Option<AiGoal>) -> AiGoalDo + Send + Sync>,
+    goal_completed: Box<Fn(&Option<AiGoal>) -> Vec<AiGoal> + Send + Sync>,
+    goal_failed: Box<Fn(&Option<AiGoal>, &AiGoalFailure) -> Vec<AiGoal> + Send + Sync>,
+}
+
+impl AiComponent {
+    pub fn new(
+        goal_do: Box<Fn(&Option<AiGoal>) -> AiGoalDo + Send + Sync>,
+        goal_completed: Box<Fn(&Option<AiGoal>) -> Vec<AiGoal> + Send + Sync>,
+        goal_failed: Box<Fn(&Option<AiGoal>, &AiGoalFailure) -> Vec<AiGoal> + Send + Sync>
+    ) -> Self {
+        AiComponent {
+            current_goal: None,
+            goals: VecDeque::new(),
+            pending_move: None,
+            rejected_moves: 0,
+            behaviour: None,
+            goal_do,
+            goal_completed,
+            goal_failed,
+        }
+    }
+
+    // goals come from the tree instead of the callbacks
+    pub fn from_behaviour(behaviour: BehaviourTree) -> Self {
+        let mut ai = AiComponent::new(
+            Box::new(|_| AiGoalDo::Continue),
+            Box::new(|_| vec![]),
+            Box::new(|_, _| vec![]),
+        );
+        ai.behaviour = Some(behaviour);
+        ai
+    }
+
+    pub fn behaviour(&self) -> Option<&BehaviourTree> {
+        self.behaviour.as_ref()
+    }
+
+    pub fn set_behaviour(&mut self, behaviour: Option<BehaviourTree>) {
+        self.behaviour = behaviour;
+    }
+
+    pub fn current_goal(&self) -> &Option<AiGoal> {
+        &self.current_goal
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum AiMessages {
+    Interact { entity: Entity, target: Entity },
+}
+
+impl Message for AiMessages { }
+
+fn is_complete(goal: &AiGoal, position: &Vector3<i32>, map: &Map) -> bool {
+    use self::AiGoal::*;
+    match goal {
+        Move { target, .. } => position == target,
+        Wait { remaining } => *remaining == 0,
+        Follow { target, distance, .. } => map.location(target)
+            .map(|location| map.distance(position, location) <= *distance)
+            .unwrap_or(false),
+        Flee { from, distance } => map.location(from)
+            .map(|location| map.distance(position, location) >= *distance)
+            .unwrap_or(true),
+        Interact { target, .. } => map.location(target)
+            .map(|location| map.distance(position, location) <= 1)
+            .unwrap_or(false),
+    }
+}
+
+fn send_move(ai: &mut AiComponent, entity: Entity, new_location: Vector3<i32>, map_messages: &mut MessageSender<MapMessage>) {
+    let (reply, receiver) = mpsc::sync_channel(1);
+
+    map_messages.send(MapMessage::Move {
+        entity: entity,
+        new_location,
+        absolute: true,
+        reply: Some(reply),
+    });
+
+    ai.pending_move = Some(PendingMove {
+        location: new_location,
+        reply: Mutex::new(receiver),
+    });
+}
+
+pub struct AiSystem {
+    scripts: ScriptCache,
+}
+
+impl AiSystem {
+    pub fn new() -> Self {
+        AiSystem {
+            scripts: ScriptCache::new(),
+        }
+    }
+}
+
+impl<'a> System<'a> for AiSystem {
+    type SystemData =  (Entities<'a>,
+                        WriteStorage<'a, AiComponent>,
+                        ReadStorage<'a, Position>,
+                        ReadStorage<'a, CollisionLayers>,
+                        ReadStorage<'a, AiScript>,
+                        ReadStorage<'a, Data>,
+                        Fetch<'a, RLock<Map>>,
+                        FetchMut<'a, MessageSender<MapMessage>>,
+                        FetchMut<'a, Gluon>,
+                        FetchMut<'a, TurnScheduler>,
+                        Fetch<'a, EventBus>);
+
+    fn run(&mut self, (entities, mut ais, positions, collision_layers, scripts, datas, map, mut map_messages, mut gluon, mut scheduler, events): Self::SystemData) {
+        use specs::Join;
+
+        let map = map.read().unwrap();
+        let AiSystem { scripts: cache } = self;
+
+        // scripts need the entity's `Data` to run
+        let script_for = |entity| match (scripts.get(entity), datas.get(entity)) {
+            (Some(script), Some(data)) => Some((script, data)),
+            _ => None,
+        };
+
+        let generate_new_goals = |ai: &mut AiComponent, script: Option<(&AiScript, &Data)>, cache: &mut ScriptCache, gluon: &mut Gluon, goal: &Option<AiGoal>| {
+            let new_goals = script
+                .and_then(|(script, data)| script.goal_completed(data, goal, cache, gluon))
+                .unwrap_or_else(|| (ai.goal_completed)(&goal));
+
+            for new_goal in new_goals {
+                ai.goals.push_back(new_goal);
+            }
+            ai.goals.pop_front()
+        };
+
+        for (entity, ai, _) in (&*entities, &mut ais, &positions).join() {
+            if !scheduler.is_turn(entity) || (scheduler.waiting_for_input() && scheduler.current() == Some(entity)) {
+                continue;
+            }
+
+            let script = script_for(entity);
+
+            let position = match map.location(&entity) {
+                Some(position) => position,
+                // it can't act until it's on the map, so its turn is skipped
+                None => {
+                    scheduler.end_turn(entity);
+                    continue;
+                },
+            };
+
+            let rejected = match ai.pending_move.take() {
+                Some(pending) => match pending.result() {
+                    Some(true) => {
+                        ai.rejected_moves = 0;
+                        None
+                    },
+                    Some(false) => {
+                        ai.rejected_moves += 1;
+                        Some(pending.location)
+                    },
+                    None => {
+                        // wait for `MapSystem` before sending another step
+                        ai.pending_move = Some(pending);
+                        continue;
+                    },
+                },
+                None => None,
+            };
+
+            let current_goal = match &ai.current_goal {
+                Some(goal) => Some(goal.clone()),
+                None => if let Some(goal) = ai.goals.pop_front() {
+                    Some(goal)
+                } else {
+                    generate_new_goals(ai, script, cache, &mut gluon, &None)
+                },
+            };
+
+            // check if the current goal is complete
+            let mut current_goal = match &current_goal {
+                Some(goal) => if is_complete(goal, position, &map) {
+                    if let AiGoal::Interact { target, .. } = goal {
+                        events.publish(AiMessages::Interact { entity, target: *target });
+                    }
+
+                    if let Some(behaviour) = &mut ai.behaviour {
+                        behaviour.finish(BehaviourStatus::Success);
+                    }
+
+                    ai.rejected_moves = 0;
+                    generate_new_goals(ai, script, cache, &mut gluon, &current_goal)
+                } else { current_goal },
+                None => None,
+            };
+
+            let mut rejected = rejected;
+
+            let command = match &mut ai.behaviour {
+                Some(behaviour) => behaviour.tick(&BehaviourContext {
+                    entity,
+                    location: *position,
+                    idle: current_goal.is_none(),
+                    map: &map,
+                    collision_layers: &collision_layers,
+                }),
+                None => BehaviourCommand::Continue,
+            };
+
+            match command {
+                BehaviourCommand::Start(goal) => {
+                    ai.goals.clear();
+                    ai.rejected_moves = 0;
+                    rejected = None;
+                    current_goal = Some(goal);
+                },
+                BehaviourCommand::Cancel => {
+                    ai.goals.clear();
+                    ai.rejected_moves = 0;
+                    rejected = None;
+                    current_goal = None;
+                },
+                BehaviourCommand::Continue => (),
+            }
+
+            let goal_do = script
+                .and_then(|(script, data)| script.goal_do(data, &current_goal, cache, &mut gluon))
+                .unwrap_or_else(|| (ai.goal_do)(&current_goal));
+
+            match goal_do {
+                AiGoalDo::Replace(goals) => {
+                    ai.goals.clear();
+                    for goal in goals {
+                        ai.goals.push_back(goal);
+                    }
+                },
+                AiGoalDo::Queue(goals) => {
+                    for goal in goals {
+                        ai.goals.push_back(goal);
+                    }
+                },
+                AiGoalDo::Continue => ()
+            };
+
+            let mut failure = None;
+
+            if let Some(location) = rejected {
+                if ai.rejected_moves >= MAX_REJECTED_MOVES {
+                    failure = Some(AiGoalFailure::MoveRejected(location, ai.rejected_moves));
+                }
+            }
+
+            use self::AiGoal::*;
+            match &mut current_goal {
+                Some(Wait { remaining }) => {
+                    *remaining = remaining.saturating_sub(1);
+                },
+                Some(Flee { from, .. }) => if failure.is_none() {
+                    // `is_complete` has already handled `from` leaving the map
+                    if let Some(threat) = map.location(from) {
+                        let current = map.distance(position, threat);
+
+                        let step = map.neighbours(position)
+                            .into_iter()
+                            .filter(|neighbour| map.can_move(entity, *neighbour, &collision_layers))
+                            .map(|neighbour| (map.distance(&neighbour, threat), neighbour))
+                            .filter(|(distance, _)| *distance > current)
+                            .max_by_key(|(distance, _)| *distance);
+
+                        match step {
+                            Some((_, new_location)) => send_move(ai, entity, new_location, &mut map_messages),
+                            None => failure = Some(AiGoalFailure::Cornered(*from)),
+                        }
+                    }
+                },
+                Some(Move { target, path, .. }) => if failure.is_none() {
+                    // the rest of the path was planned from a cell we never reached
+                    if rejected.is_some() {
+                        path.clear();
+                    }
+
+                    if path.is_empty() {
+                        match map.find_path(entity, *position, *target, &collision_layers) {
+                            Ok(new_path) => *path = new_path,
+                            Err(err) => failure = Some(AiGoalFailure::Path(err)),
+                        };
+                    }
+
+                    if failure.is_none() {
+                        if let Some(new_location) = path.pop_front() {
+                            send_move(ai, entity, new_location, &mut map_messages);
+                        }
+                    }
+                },
+                Some(Follow { target, distance, path }) => if failure.is_none() {
+                    failure = approach(ai, entity, position, *target, *distance, path, rejected.is_some(), &map, &collision_layers, &mut map_messages).err();
+                },
+                Some(Interact { target, path }) => if failure.is_none() {
+                    failure = approach(ai, entity, position, *target, 1, path, rejected.is_some(), &map, &collision_layers, &mut map_messages).err();
+                },
+                None => (),
+            }
+
+            if let Some(failure) = failure {
+                if let Some(behaviour) = &mut ai.behaviour {
+                    behaviour.finish(BehaviourStatus::Failure);
+                }
+
+                let new_goals = script
+                    .and_then(|(script, data)| script.goal_failed(data, &current_goal, &failure, cache, &mut gluon))
+                    .unwrap_or_else(|| (ai.goal_failed)(&current_goal, &failure));
+
+                for new_goal in new_goals {
+                    ai.goals.push_back(new_goal);
+                }
+                ai.rejected_moves = 0;
+                current_goal = None;
+            }
+
+            ai.current_goal = current_goal;
+
+            // otherwise `MapSystem` ends the turn when it handles the move
+            if ai.pending_move.is_none() {
+                scheduler.end_turn(entity);
+            }
+        }
+    }
+}
+
+// moves along `path` towards the closest free cell to `target`, re-planning when `target` has moved away from the end of it
+fn approach<'a>(
+    ai: &mut AiComponent,
+    entity: Entity,
+    position: &Vector3<i32>,
+    target: Entity,
+    distance: i32,
+    path: &mut VecDeque<Vector3<i32>>,
+    rejected: bool,
+    map: &Map,
+    collision_layers: &ReadStorage<'a, CollisionLayers>,
+    map_messages: &mut MessageSender<MapMessage>,
+) -> Result<(), AiGoalFailure> {
+    let target_location = match map.location(&target) {
+        Some(location) => *location,
+        None => return Err(AiGoalFailure::TargetLost(target)),
+    };
+
+    let stale = path.back()
+        .map(|end| map.distance(end, &target_location) > distance)
+        .unwrap_or(true);
+
+    if rejected || stale {
+        let destination = match map.nearest_free_cell(entity, &target_location, collision_layers) {
+            Some(destination) => destination,
+            None => return Err(AiGoalFailure::Path(PathError::TargetBlocked(target_location))),
+        };
+
+        *path = map.find_path(entity, *position, destination, collision_layers)
+            .map_err(|err| AiGoalFailure::Path(err))?;
+    }
+
+    if let Some(new_location) = path.pop_front() {
+        send_move(ai, entity, new_location, map_messages);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,276 @@
+use std::{ collections::{ HashMap, HashSet, VecDeque }, path::PathBuf, sync::Arc };
+use cgmath::Vector3;
+use failure::Error;
+use ron;
+use specs::{ Entity, ReadStorage };
+use crate::{ AiGoal, CollisionLayer, CollisionLayers, LayerFilter, Map, Resources, RLock };
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum TargetSelector {
+    // the closest entity on any of `layers`, not including the entity itself
+    Nearest { layers: HashSet<CollisionLayer>, radius: i32 },
+}
+
+impl TargetSelector {
+    fn select(&self, context: &BehaviourContext) -> Option<Entity> {
+        match self {
+            TargetSelector::Nearest { layers, radius } => {
+                let filter = LayerFilter::new(layers, context.collision_layers);
+                context.map.nearest_entity(&context.location, *radius, Some(&filter), Some(context.entity))
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Condition {
+    // the entity doesn't have a goal
+    Idle,
+    Near { target: TargetSelector, distance: i32 },
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    fn check(&self, context: &BehaviourContext) -> bool {
+        match self {
+            Condition::Idle => context.idle,
+            Condition::Near { target, distance } => target.select(context)
+                .and_then(|target| context.map.location(&target))
+                .map(|location| context.map.distance(&context.location, location) <= *distance)
+                .unwrap_or(false),
+            Condition::Not(condition) => !condition.check(context),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum GoalDesc {
+    MoveTo(i32, i32, i32),
+    // in ticks
+    Wait(u32),
+    Follow { target: TargetSelector, distance: i32 },
+    Flee { from: TargetSelector, distance: i32 },
+    Interact(TargetSelector),
+}
+
+impl GoalDesc {
+    // `None` if there isn't anything to target
+    fn to_goal(&self, context: &BehaviourContext) -> Option<AiGoal> {
+        let goal = match self {
+            GoalDesc::MoveTo(x, y, z) => AiGoal::Move {
+                start: context.location,
+                target: Vector3::new(*x, *y, *z),
+                path: VecDeque::new(),
+            },
+            GoalDesc::Wait(ticks) => AiGoal::Wait { remaining: *ticks },
+            GoalDesc::Follow { target, distance } => AiGoal::Follow {
+                target: target.select(context)?,
+                distance: *distance,
+                path: VecDeque::new(),
+            },
+            GoalDesc::Flee { from, distance } => AiGoal::Flee {
+                from: from.select(context)?,
+                distance: *distance,
+            },
+            GoalDesc::Interact(target) => AiGoal::Interact {
+                target: target.select(context)?,
+                path: VecDeque::new(),
+            },
+        };
+
+        Some(goal)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Behaviour {
+    // runs each child until one doesn't succeed
+    Sequence(Vec<Behaviour>),
+    // runs each child until one doesn't fail
+    Selector(Vec<Behaviour>),
+    Condition(Condition),
+    // fails for `ticks` after the child succeeds
+    Cooldown { ticks: u64, child: Box<Behaviour> },
+    // runs until the goal is completed or fails
+    Goal(GoalDesc),
+}
+
+impl Behaviour {
+    // the number of nodes in this subtree
+    fn size(&self) -> usize {
+        1 + match self {
+            Behaviour::Sequence(children) | Behaviour::Selector(children) => children.iter().map(|child| child.size()).sum(),
+            Behaviour::Cooldown { child, .. } => child.size(),
+            Behaviour::Condition(_) | Behaviour::Goal(_) => 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BehaviourStatus {
+    Success,
+    Failure,
+    Running,
+}
+
+pub(crate) struct BehaviourContext<'s, 'a: 's> {
+    pub entity: Entity,
+    pub location: Vector3<i32>,
+    pub idle: bool,
+    pub map: &'s Map,
+    pub collision_layers: &'s ReadStorage<'a, CollisionLayers>,
+}
+
+pub(crate) enum BehaviourCommand {
+    Start(AiGoal),
+    // the goal that was running isn't wanted anymore
+    Cancel,
+    Continue,
+}
+
+struct Tick {
+    next_id: usize,
+    started: Option<AiGoal>,
+    reached_active: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct BehaviourTree {
+    root: Arc<Behaviour>,
+    // how many times the tree has been ticked
+    tick: u64,
+    // keyed by the pre-order index of the `Cooldown` node
+    cooldowns: HashMap<usize, u64>,
+    // the `Goal` node whose goal is running
+    active: Option<usize>,
+    outcome: Option<BehaviourStatus>,
+}
+
+impl BehaviourTree {
+    pub fn new(root: Behaviour) -> Self {
+        Self {
+            root: Arc::new(root),
+            tick: 0,
+            cooldowns: HashMap::new(),
+            active: None,
+            outcome: None,
+        }
+    }
+
+    pub fn load(path: &PathBuf, resources: &RLock<Resources>) -> Result<Self, Error> {
+        let resources = resources.read().unwrap();
+        let desc = resources.get_string(path)?;
+        let root = ron::de::from_str::<Behaviour>(&desc)?;
+
+        Ok(BehaviourTree::new(root))
+    }
+
+    pub fn root(&self) -> &Behaviour {
+        &self.root
+    }
+
+    pub(crate) fn finish(&mut self, status: BehaviourStatus) {
+        if self.active.is_some() {
+            self.outcome = Some(status);
+        }
+    }
+
+    pub(crate) fn tick(&mut self, context: &BehaviourContext) -> BehaviourCommand {
+        self.tick += 1;
+
+        let root = self.root.clone();
+        let mut tick = Tick {
+            next_id: 0,
+            started: None,
+            reached_active: false,
+        };
+
+        self.tick_node(&root, context, &mut tick);
+
+        match tick.started {
+            Some(goal) => BehaviourCommand::Start(goal),
+            None => if self.active.is_some() && !tick.reached_active {
+                self.active = None;
+                self.outcome = None;
+                BehaviourCommand::Cancel
+            } else {
+                BehaviourCommand::Continue
+            },
+        }
+    }
+
+    fn tick_node(&mut self, node: &Behaviour, context: &BehaviourContext, tick: &mut Tick) -> BehaviourStatus {
+        let id = tick.next_id;
+        tick.next_id += 1;
+
+        let status = self.tick_children(id, node, context, tick);
+
+        // skipped children still take up ids, so that every node keeps the same id between ticks
+        tick.next_id = id + node.size();
+        status
+    }
+
+    fn tick_children(&mut self, id: usize, node: &Behaviour, context: &BehaviourContext, tick: &mut Tick) -> BehaviourStatus {
+        match node {
+            Behaviour::Sequence(children) => {
+                for child in children {
+                    match self.tick_node(child, context, tick) {
+                        BehaviourStatus::Success => (),
+                        status => return status,
+                    }
+                }
+                BehaviourStatus::Success
+            },
+            Behaviour::Selector(children) => {
+                for child in children {
+                    match self.tick_node(child, context, tick) {
+                        BehaviourStatus::Failure => (),
+                        status => return status,
+                    }
+                }
+                BehaviourStatus::Failure
+            },
+            Behaviour::Condition(condition) => if condition.check(context) {
+                BehaviourStatus::Success
+            } else {
+                BehaviourStatus::Failure
+            },
+            Behaviour::Cooldown { ticks, child } => {
+                if let Some(ready) = self.cooldowns.get(&id) {
+                    if self.tick < *ready {
+                        return BehaviourStatus::Failure;
+                    }
+                }
+
+                let status = self.tick_node(child, context, tick);
+                if status == BehaviourStatus::Success {
+                    self.cooldowns.insert(id, self.tick + ticks);
+                }
+                status
+            },
+            Behaviour::Goal(desc) => {
+                if self.active == Some(id) {
+                    tick.reached_active = true;
+
+                    return match self.outcome.take() {
+                        Some(status) => {
+                            self.active = None;
+                            status
+                        },
+                        None => BehaviourStatus::Running,
+                    };
+                }
+
+                match desc.to_goal(context) {
+                    Some(goal) => {
+                        self.active = Some(id);
+                        self.outcome = None;
+                        tick.started = Some(goal);
+                        BehaviourStatus::Running
+                    },
+                    None => BehaviourStatus::Failure,
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,24 @@
+mod ai;
+pub use self::ai::{
+    AiComponent,
+    AiGoalDo,
+    AiGoal,
+    AiGoalFailure,
+    AiMessages,
+    AiSystem,
+};
+
+mod script;
+pub use self::script::AiScript;
+pub(crate) use self::script::ScriptCache;
+
+mod behaviour;
+pub use self::behaviour::{
+    Behaviour,
+    BehaviourStatus,
+    BehaviourTree,
+    Condition,
+    GoalDesc,
+    TargetSelector,
+};
+pub(crate) use self::behaviour::{ BehaviourCommand, BehaviourContext };
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use gluon::{ vm::api::{ Function, Getable, VmType }, RootedThread };
+use specs::VecStorage;
+use crate::{ AiGoal, AiGoalDo, AiGoalFailure, Data };
+use crate::gluon_api::prelude;
+use crate::opal::Gluon;
+
+// Gluon replacements for the `AiComponent` callbacks, each script is called with `data`
+// and can get the goal with `ai.goal.get data` and the failure with `ai.failure data`.
+// Callbacks without a script fall back to the closures given to `AiComponent::new`.
+#[derive(Component, Serialize, Deserialize, Clone, Debug, Default)]
+#[component(VecStorage)]
+pub struct AiScript {
+    pub name: String,
+    pub goal_do: Option<String>,
+    pub goal_completed: Option<String>,
+    pub goal_failed: Option<String>,
+}
+
+type ScriptFunction<R> = Function<RootedThread, fn(Data) -> R>;
+
+// each compiled function by name, with the script it was compiled from
+type ScriptFunctions<R> = HashMap<String, (String, Option<ScriptFunction<R>>)>;
+
+// scripts are compiled the first time they run rather than every tick, owned by `AiSystem`
+#[derive(Default)]
+pub(crate) struct ScriptCache {
+    goal_do: ScriptFunctions<AiGoalDo>,
+    goals: ScriptFunctions<Vec<AiGoal>>,
+}
+
+impl ScriptCache {
+    pub(crate) fn new() -> Self {
+        ScriptCache::default()
+    }
+}
+
+// `None` if the script failed, a script that doesn't compile isn't compiled again until it changes
+fn run<R>(functions: &mut ScriptFunctions<R>, name: &str, script: &str, data: &Data, gluon: &mut Gluon) -> Option<R> where R: VmType + for<'vm> Getable<'vm> {
+    let is_compiled = functions.get(name)
+        .map(|(source, _)| source == script)
+        .unwrap_or(false);
+
+    if !is_compiled {
+        let Gluon { compiler, thread } = gluon;
+
+        let function = match prelude::add_prelude::<Data, R>(&vec!["data"][..], name, script, compiler, thread.clone()) {
+            Ok(function) => Some(function),
+            Err(err) => {
+                println!("Gluon Error: {}", err);
+                None
+            },
+        };
+        functions.insert(name.to_owned(), (script.to_owned(), function));
+    }
+
+    let function = functions.get_mut(name)?.1.as_mut()?;
+
+    match function.call(data.clone()) {
+        Ok(result) => Some(result),
+        Err(err) => {
+            println!("Gluon Error: {}", err);
+            None
+        },
+    }
+}
+
+fn set_context(data: &Data, goal: &Option<AiGoal>, failure: Option<&AiGoalFailure>) {
+    match goal {
+        Some(goal) => { data.insert(goal.clone()); },
+        None => { data.remove::<AiGoal>(); },
+    }
+
+    match failure {
+        Some(failure) => { data.insert(failure.clone()); },
+        None => { data.remove::<AiGoalFailure>(); },
+    }
+}
+
+impl AiScript {
+    // `None` if there isn't a `goal_do` script
+    pub(crate) fn goal_do(&self, data: &Data, goal: &Option<AiGoal>, scripts: &mut ScriptCache, gluon: &mut Gluon) -> Option<AiGoalDo> {
+        let script = self.goal_do.as_ref()?;
+        set_context(data, goal, None);
+
+        let goal_do = run(&mut scripts.goal_do, &format!("{}_goal_do", self.name), script, data, gluon);
+        Some(goal_do.unwrap_or(AiGoalDo::Continue))
+    }
+
+    pub(crate) fn goal_completed(&self, data: &Data, goal: &Option<AiGoal>, scripts: &mut ScriptCache, gluon: &mut Gluon) -> Option<Vec<AiGoal>> {
+        let script = self.goal_completed.as_ref()?;
+        set_context(data, goal, None);
+
+        let goals = run(&mut scripts.goals, &format!("{}_goal_completed", self.name), script, data, gluon);
+        Some(goals.unwrap_or_else(|| vec![]))
+    }
+
+    pub(crate) fn goal_failed(&self, data: &Data, goal: &Option<AiGoal>, failure: &AiGoalFailure, scripts: &mut ScriptCache, gluon: &mut Gluon) -> Option<Vec<AiGoal>> {
+        let script = self.goal_failed.as_ref()?;
+        set_context(data, goal, Some(failure));
+
+        let goals = run(&mut scripts.goals, &format!("{}_goal_failed", self.name), script, data, gluon);
+        Some(goals.unwrap_or_else(|| vec![]))
+    }
+}
@@ -1,7 +1,7 @@
 use std::{ collections::HashMap, fs::File, path::PathBuf };
 use failure::Error;
 use ron;
-use crate::{ ShaderKey };
+use crate::{ MapTopology, PlacementPolicy, ShaderKey };
 
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -10,6 +10,20 @@ pub struct Config {
     pub window_dimensions: (u32, u32),
     pub shaders: HashMap<ShaderKey, PathBuf>,
     pub map_dimensions: (i32, i32, i32),
+    pub map_topology: MapTopology,
+    pub terrain: Option<PathBuf>,
+    // loaded when the `Opal` is built, replacing `map_dimensions`, `map_topology` and `terrain`
+    pub level: Option<PathBuf>,
+    pub placement_policy: PlacementPolicy,
+    // RON files in the resources, each a map of prefab names to prefabs
+    pub prefabs: Vec<PathBuf>,
+    pub turn_based: bool,
+    // bindings from actions to keys and buttons, in the resources
+    pub input_map: Option<PathBuf>,
+    // picking tests the triangles of a model after its bounding box
+    pub pick_triangles: bool,
+    // simulation ticks per second
+    pub tick_rate: f32,
     pub resources: Vec<PathBuf>,
     pub fonts: Vec<PathBuf>,
     pub font_resolution: u32,
@@ -45,6 +59,42 @@ impl Config {
             self.map_dimensions = map_dimensions;
         }
 
+        if let Some(map_topology) = other.map_topology {
+            self.map_topology = map_topology;
+        }
+
+        if let Some(terrain) = other.terrain {
+            self.terrain = Some(terrain);
+        }
+
+        if let Some(level) = other.level {
+            self.level = Some(level);
+        }
+
+        if let Some(placement_policy) = other.placement_policy {
+            self.placement_policy = placement_policy;
+        }
+
+        if let Some(prefabs) = other.prefabs {
+            self.prefabs.extend(prefabs);
+        }
+
+        if let Some(turn_based) = other.turn_based {
+            self.turn_based = turn_based;
+        }
+
+        if let Some(input_map) = other.input_map {
+            self.input_map = Some(input_map);
+        }
+
+        if let Some(pick_triangles) = other.pick_triangles {
+            self.pick_triangles = pick_triangles;
+        }
+
+        if let Some(tick_rate) = other.tick_rate {
+            self.tick_rate = tick_rate;
+        }
+
         if let Some(resources) = other.resources {
             let mut resources = resources.clone();
             resources.extend(self.resources);
@@ -69,6 +119,15 @@ pub struct ConfigBuilder {
     pub window_dimensions: Option<(u32, u32)>,
     pub shaders: Option<HashMap<ShaderKey, PathBuf>>,
     pub map_dimensions: Option<(i32, i32, i32)>,
+    pub map_topology: Option<MapTopology>,
+    pub terrain: Option<PathBuf>,
+    pub level: Option<PathBuf>,
+    pub placement_policy: Option<PlacementPolicy>,
+    pub prefabs: Option<Vec<PathBuf>>,
+    pub turn_based: Option<bool>,
+    pub input_map: Option<PathBuf>,
+    pub pick_triangles: Option<bool>,
+    pub tick_rate: Option<f32>,
     pub resources: Option<Vec<PathBuf>>,
     pub fonts: Option<Vec<PathBuf>>,
     pub font_resolution: Option<u32>,
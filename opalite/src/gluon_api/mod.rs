@@ -1,4 +1,4 @@
-use std::{ ops::Deref, sync::{ Arc, Mutex } };
+use std::{ collections::VecDeque, ops::Deref, sync::{ Arc, Mutex } };
 use anymap::{ any, Map as AnyMap };
 use gluon::{
     self,
@@ -13,7 +13,7 @@ use gluon::{
     Thread,
 };
 use specs::{ self, Fetch, FetchMut, ReadStorage, System, VecStorage, WriteStorage };
-use crate::{ Map, RLock };
+use crate::{ AiGoal, AiGoalDo, AiGoalFailure, Map, PrefabSpawner, RLock, Selection, Viewshed };
 use crate::opal::{ Gluon, GluonUi };
 use crate::InitialPosition;
 
@@ -25,6 +25,8 @@ pub mod conrod;
 register_gluon!(Map);
 register_data!(Map);
 
+register_gluon!(Selection);
+
 impl<T> RLock<T> where T: Userdata + Clone {
     pub fn get_from_data(map: Data) -> Option<Self> {
         map.get()
@@ -88,6 +90,25 @@ register_gluon!(Entity);
 register_gluon!(InitialPosition);
 register_data!(InitialPosition);
 
+register_gluon!(Viewshed);
+register_data!(Viewshed);
+
+register_gluon!(AiGoal);
+register_data!(AiGoal);
+
+register_gluon!(AiGoalDo);
+
+register_gluon!(PrefabSpawner);
+register_data!(PrefabSpawner);
+
+fn vec3_to_cell(v: self::cgmath::Vec3) -> ::cgmath::Vector3<i32> {
+    ::cgmath::Vector3::new(v.x() as i32, v.y() as i32, v.z() as i32)
+}
+
+fn cell_to_vec3(cell: &::cgmath::Vector3<i32>) -> self::cgmath::Vec3 {
+    self::cgmath::Vec3::new(cell.x as f64, cell.y as f64, cell.z as f64)
+}
+
 #[derive(Component, Clone, Debug)]
 pub struct Data(Arc<Mutex<AnyMap<any::CloneAny + Send + Sync>>>);
 
@@ -127,6 +148,14 @@ impl Data {
 #[derive(Component, Copy, Clone, Debug)]
 pub struct RequireMap;
 
+// puts the `PrefabSpawner` in the entity's `Data`
+#[derive(Component, Copy, Clone, Debug)]
+pub struct RequirePrefabs;
+
+// puts the `Selection` in the entity's `Data`
+#[derive(Component, Copy, Clone, Debug)]
+pub struct RequireSelection;
+
 #[derive(Component, Clone, Debug)]
 pub struct DataReference {
     pub entity: Option<specs::Entity>,
@@ -155,7 +184,12 @@ pub fn register_opalite_api(vm: &gluon::Thread) {
     vm.register_type::<Entity>("Entity", &[]).unwrap();
     vm.register_type::<Map>("Map", &[]).unwrap();
 
+    vm.register_type::<AiGoal>("AiGoal", &[]).unwrap();
+    vm.register_type::<AiGoalDo>("AiGoalDo", &[]).unwrap();
     vm.register_type::<InitialPosition>("InitialPosition", &[]).unwrap();
+    vm.register_type::<PrefabSpawner>("PrefabSpawner", &[]).unwrap();
+    vm.register_type::<Selection>("Selection", &[]).unwrap();
+    vm.register_type::<Viewshed>("Viewshed", &[]).unwrap();
 
     gluon::import::add_extern_module(vm, "initial_position", |vm: &gluon::Thread| {
         vm::ExternModule::new(vm, record!(
@@ -183,14 +217,179 @@ pub fn register_opalite_api(vm: &gluon::Thread) {
     });
 
     fn map_location(map: &Map, entity: &Entity) -> Option<self::cgmath::Vec3> {
-        map.location(&entity.0).map(|l| {
-            self::cgmath::Vec3(::cgmath::Vector3::new(l.x as f64, l.y as f64, l.z as f64))
-        })
+        map.location(&entity.0).map(cell_to_vec3)
+    }
+
+    fn map_distance(map: &Map, a: self::cgmath::Vec3, b: self::cgmath::Vec3) -> i32 {
+        map.distance(&vec3_to_cell(a), &vec3_to_cell(b))
+    }
+
+    // scripts don't have the collision layers, so only terrain blocks sight
+    fn map_line_of_sight(map: &Map, from: self::cgmath::Vec3, to: self::cgmath::Vec3) -> bool {
+        map.line_of_sight(&vec3_to_cell(from), &vec3_to_cell(to), |cell| map.terrain().is_opaque(cell))
+    }
+
+    fn map_field_of_view(map: &Map, origin: self::cgmath::Vec3, radius: i32) -> Vec<self::cgmath::Vec3> {
+        map.field_of_view(&vec3_to_cell(origin), radius, |cell| map.terrain().is_opaque(cell))
+            .iter()
+            .map(cell_to_vec3)
+            .collect()
+    }
+
+    fn map_entities_in_radius(map: &Map, center: self::cgmath::Vec3, radius: i32) -> Vec<Entity> {
+        map.entities_in_radius(&vec3_to_cell(center), radius, None)
+            .into_iter()
+            .map(Entity)
+            .collect()
+    }
+
+    fn map_entities_in_box(map: &Map, min: self::cgmath::Vec3, max: self::cgmath::Vec3) -> Vec<Entity> {
+        map.entities_in_box(&vec3_to_cell(min), &vec3_to_cell(max), None)
+            .into_iter()
+            .map(Entity)
+            .collect()
+    }
+
+    fn viewshed_can_see(viewshed: &Viewshed, cell: self::cgmath::Vec3) -> bool {
+        viewshed.can_see(&vec3_to_cell(cell))
+    }
+
+    fn viewshed_cells(viewshed: &Viewshed) -> Vec<self::cgmath::Vec3> {
+        viewshed.visible().iter().map(cell_to_vec3).collect()
+    }
+
+    fn ai_move(start: self::cgmath::Vec3, target: self::cgmath::Vec3) -> AiGoal {
+        AiGoal::Move { start: vec3_to_cell(start), target: vec3_to_cell(target), path: VecDeque::new() }
+    }
+
+    fn ai_wait(ticks: i32) -> AiGoal {
+        AiGoal::Wait { remaining: ticks.max(0) as u32 }
+    }
+
+    fn ai_follow(target: Entity, distance: i32) -> AiGoal {
+        AiGoal::Follow { target: target.0, distance, path: VecDeque::new() }
+    }
+
+    fn ai_flee(from: Entity, distance: i32) -> AiGoal {
+        AiGoal::Flee { from: from.0, distance }
+    }
+
+    fn ai_interact(target: Entity) -> AiGoal {
+        AiGoal::Interact { target: target.0, path: VecDeque::new() }
+    }
+
+    fn ai_kind(goal: &AiGoal) -> String {
+        match goal {
+            AiGoal::Move { .. } => "move",
+            AiGoal::Wait { .. } => "wait",
+            AiGoal::Follow { .. } => "follow",
+            AiGoal::Flee { .. } => "flee",
+            AiGoal::Interact { .. } => "interact",
+        }.to_owned()
+    }
+
+    fn ai_location(goal: &AiGoal) -> Option<self::cgmath::Vec3> {
+        match goal {
+            AiGoal::Move { target, .. } => Some(cell_to_vec3(target)),
+            _ => None,
+        }
+    }
+
+    fn ai_entity(goal: &AiGoal) -> Option<Entity> {
+        match goal {
+            AiGoal::Follow { target, .. } | AiGoal::Interact { target, .. } => Some(Entity(*target)),
+            AiGoal::Flee { from, .. } => Some(Entity(*from)),
+            _ => None,
+        }
+    }
+
+    fn ai_replace(goals: Vec<AiGoal>) -> AiGoalDo {
+        AiGoalDo::Replace(goals)
+    }
+
+    fn ai_queue(goals: Vec<AiGoal>) -> AiGoalDo {
+        AiGoalDo::Queue(goals)
+    }
+
+    fn ai_failure(data: Data) -> Option<String> {
+        data.get::<AiGoalFailure>().map(|failure| failure.to_string())
+    }
+
+    gluon::import::add_extern_module(vm, "ai", |vm: &gluon::Thread| {
+        vm::ExternModule::new(vm, record!(
+            goal => record!(
+                move_to => primitive!(2 ai_move),
+                wait => primitive!(1 ai_wait),
+                follow => primitive!(2 ai_follow),
+                flee => primitive!(2 ai_flee),
+                interact => primitive!(1 ai_interact),
+                kind => primitive!(1 ai_kind),
+                location => primitive!(1 ai_location),
+                entity => primitive!(1 ai_entity),
+                get => primitive!(1 AiGoal::get_from_data),
+                contains => primitive!(1 AiGoal::contains_in_data),
+            ),
+            goal_do => record!(
+                replace => primitive!(1 ai_replace),
+                queue => primitive!(1 ai_queue),
+                keep => AiGoalDo::Continue,
+            ),
+            failure => primitive!(1 ai_failure),
+        ))
+    });
+
+    fn prefab_spawn(spawner: &PrefabSpawner, name: String, position: self::cgmath::Vec3) {
+        spawner.spawn(name, vec3_to_cell(position));
+    }
+
+    gluon::import::add_extern_module(vm, "prefab", |vm: &gluon::Thread| {
+        vm::ExternModule::new(vm, record!(
+            spawn => primitive!(3 prefab_spawn),
+            data => record!(
+                get => primitive!(1 PrefabSpawner::get_from_data),
+                contains => primitive!(1 PrefabSpawner::contains_in_data),
+            ),
+        ))
+    });
+
+    fn selection_hovered(selection: &Selection) -> Option<Entity> {
+        selection.hovered().map(Entity)
+    }
+
+    fn selection_selected(selection: &Selection) -> Option<Entity> {
+        selection.selected().map(Entity)
     }
 
+    fn selection_cell(selection: &Selection) -> Option<self::cgmath::Vec3> {
+        selection.cell().map(|cell| cell_to_vec3(&cell))
+    }
+
+    gluon::import::add_extern_module(vm, "selection", |vm: &gluon::Thread| {
+        vm::ExternModule::new(vm, record!(
+            hovered => primitive!(1 selection_hovered),
+            selected => primitive!(1 selection_selected),
+            cell => primitive!(1 selection_cell),
+            data => record!(
+                get => primitive!(1 RLock::<Selection>::get_from_data),
+                contains => primitive!(1 RLock::<Selection>::contains_in_data),
+            ),
+        ))
+    });
+
     gluon::import::add_extern_module(vm, "map", |vm: &gluon::Thread| {
         vm::ExternModule::new(vm, record!(
             location => primitive!(2 map_location),
+            distance => primitive!(3 map_distance),
+            line_of_sight => primitive!(3 map_line_of_sight),
+            field_of_view => primitive!(3 map_field_of_view),
+            entities_in_radius => primitive!(3 map_entities_in_radius),
+            entities_in_box => primitive!(3 map_entities_in_box),
+            viewshed => record!(
+                can_see => primitive!(2 viewshed_can_see),
+                cells => primitive!(1 viewshed_cells),
+                get => primitive!(1 Viewshed::get_from_data),
+                contains => primitive!(1 Viewshed::contains_in_data),
+            ),
             data => record!(
                 insert => primitive!(2 RLock::<Map>::insert_to_data),
                 get => primitive!(1 RLock::<Map>::get_from_data),
@@ -223,6 +422,50 @@ impl<'a> System<'a> for RequireMapSystem {
     }
 }
 
+pub struct RequirePrefabsSystem;
+
+impl RequirePrefabsSystem {
+    pub fn new() -> Self {
+        RequirePrefabsSystem
+    }
+}
+
+impl<'a> System<'a> for RequirePrefabsSystem {
+    type SystemData =  (ReadStorage<'a, RequirePrefabs>,
+                        ReadStorage<'a, Data>,
+                        Fetch<'a, PrefabSpawner>);
+
+    fn run(&mut self, (require_prefabs, datas, spawner): Self::SystemData) {
+        use specs::Join;
+
+        for (_, data) in (&require_prefabs, &datas).join() {
+            data.insert(spawner.clone());
+        }
+    }
+}
+
+pub struct RequireSelectionSystem;
+
+impl RequireSelectionSystem {
+    pub fn new() -> Self {
+        RequireSelectionSystem
+    }
+}
+
+impl<'a> System<'a> for RequireSelectionSystem {
+    type SystemData =  (ReadStorage<'a, RequireSelection>,
+                        ReadStorage<'a, Data>,
+                        Fetch<'a, RLock<Selection>>);
+
+    fn run(&mut self, (require_selections, datas, selection): Self::SystemData) {
+        use specs::Join;
+
+        for (_, data) in (&require_selections, &datas).join() {
+            data.insert(selection.clone());
+        }
+    }
+}
+
 pub struct DataReferenceSystem;
 
 impl DataReferenceSystem {
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
-use winit::{ ElementState, MouseButton };
-use crate::{ Message, MessageSender };
+use winit::{ ElementState, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode };
+use crate::{ Action, ActionState, EventBus, InputMap, Message, MessageSender, ReplayHandle, RLock, WLock };
+use crate::input_map::ActionMapper;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum InputEvent {
@@ -12,6 +13,17 @@ pub enum InputEvent {
         y: f64,
     },
     MouseCoordinates { x: f64, y: f64 },
+    MouseScrolled { delta: MouseScrollDelta },
+    Keyboard {
+        state: ElementState,
+        scancode: u32,
+        virtual_keycode: Option<VirtualKeyCode>,
+        modifiers: ModifiersState,
+    },
+    // a character typed into the window, after the keyboard layout has been applied
+    Text { character: char },
+    Focused { focused: bool },
+    Resized { width: u32, height: u32 },
 }
 
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
@@ -19,6 +31,11 @@ pub enum InputEventType {
     MouseClicked,
     MouseClickedWithCoordinates,
     MouseCoordinates,
+    MouseScrolled,
+    Keyboard,
+    Text,
+    Focused,
+    Resized,
 }
 
 impl From<InputEvent> for InputEventType {
@@ -27,12 +44,46 @@ impl From<InputEvent> for InputEventType {
             InputEvent::MouseClicked { .. } => InputEventType::MouseClicked,
             InputEvent::MouseClickedWithCoordinates { .. } => InputEventType::MouseClickedWithCoordinates,
             InputEvent::MouseCoordinates { .. } => InputEventType::MouseCoordinates,
+            InputEvent::MouseScrolled { .. } => InputEventType::MouseScrolled,
+            InputEvent::Keyboard { .. } => InputEventType::Keyboard,
+            InputEvent::Text { .. } => InputEventType::Text,
+            InputEvent::Focused { .. } => InputEventType::Focused,
+            InputEvent::Resized { .. } => InputEventType::Resized,
         }
     }
 }
 
 impl Message for InputEvent { }
 
+macro_rules! virtual_keys {
+    ($($key:ident),* $(,)*) => (
+        // the inverse of formatting a `VirtualKeyCode` with `Debug`
+        pub fn virtual_key_from_name(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None,
+            }
+        }
+    )
+}
+
+// winit's key codes can't be serialized, so they're saved by name
+virtual_keys! {
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, F13, F14, F15,
+    Snapshot, Scroll, Pause, Insert, Home, Delete, End, PageDown, PageUp,
+    Left, Up, Right, Down, Back, Return, Space, Compose, Numlock,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+    AbntC1, AbntC2, Add, Apostrophe, Apps, At, Ax, Backslash, Calculator, Capital, Colon, Comma, Convert,
+    Decimal, Divide, Equals, Grave, Kana, Kanji, LAlt, LBracket, LControl, LMenu, LShift, LWin, Mail,
+    MediaSelect, MediaStop, Minus, Multiply, Mute, MyComputer, NavigateForward, NavigateBackward,
+    NextTrack, NoConvert, NumpadComma, NumpadEnter, NumpadEquals, OEM102, Period, PlayPause, Power,
+    PrevTrack, RAlt, RBracket, RControl, RMenu, RShift, RWin, Semicolon, Slash, Sleep, Stop, Subtract,
+    Sysrq, Tab, Underline, Unlabeled, VolumeDown, VolumeUp, Wake, WebBack, WebFavorites, WebForward,
+    WebHome, WebRefresh, WebSearch, WebStop, Yen,
+}
+
 struct MouseState {
     x: f64,
     y: f64,
@@ -41,6 +92,10 @@ struct MouseState {
 pub struct InputEventHandler {
     handlers: HashMap<InputEventType, Vec<MessageSender<InputEvent>>>,
     mouse_state: MouseState,
+    replay: Option<ReplayHandle>,
+    event_bus: Option<EventBus>,
+    actions: ActionMapper,
+    action_state: WLock<ActionState>,
 }
 
 impl InputEventHandler {
@@ -48,6 +103,50 @@ impl InputEventHandler {
         Self {
             handlers: HashMap::new(),
             mouse_state: MouseState { x: 0.0, y: 0.0 },
+            replay: None,
+            event_bus: None,
+            actions: ActionMapper::new(InputMap::new()),
+            action_state: WLock::new(ActionState::new()),
+        }
+    }
+
+    // every event passed to `send` is recorded while the replay is recording
+    pub fn set_replay(&mut self, replay: Option<ReplayHandle>) {
+        self.replay = replay;
+    }
+
+    // every event is also published on the bus, for systems that subscribe to `InputEvent`
+    pub fn set_event_bus(&mut self, event_bus: Option<EventBus>) {
+        self.event_bus = event_bus;
+    }
+
+    pub fn input_map(&self) -> &InputMap {
+        &self.actions.input_map
+    }
+
+    // rebinding takes effect with the next input event or tick
+    pub fn input_map_mut(&mut self) -> &mut InputMap {
+        &mut self.actions.input_map
+    }
+
+    pub fn set_input_map(&mut self, input_map: InputMap) {
+        self.actions.input_map = input_map;
+    }
+
+    pub fn action_state(&self) -> RLock<ActionState> {
+        self.action_state.get_reader()
+    }
+
+    pub(crate) fn end_tick(&mut self) {
+        let actions = self.actions.end_tick(&mut self.action_state.write().unwrap());
+        self.publish_actions(actions);
+    }
+
+    fn publish_actions(&self, actions: Vec<Action>) {
+        if let Some(event_bus) = &self.event_bus {
+            for action in actions {
+                event_bus.publish(action);
+            }
         }
     }
 
@@ -58,8 +157,16 @@ impl InputEventHandler {
     }
 
     pub fn send(&mut self, event: InputEvent) {
+        if let Some(replay) = &self.replay {
+            replay.record_input(event);
+        }
+
+        self.dispatch(event);
+    }
+
+    fn dispatch(&mut self, event: InputEvent) {
         match event {
-            InputEvent::MouseClicked { state, button } => self.send(InputEvent::MouseClickedWithCoordinates {
+            InputEvent::MouseClicked { state, button } => self.dispatch(InputEvent::MouseClickedWithCoordinates {
                 state, button,
                 x: self.mouse_state.x,
                 y: self.mouse_state.y,
@@ -68,6 +175,13 @@ impl InputEventHandler {
             _ => (),
         };
 
+        if let Some(event_bus) = &self.event_bus {
+            event_bus.publish(event);
+        }
+
+        let actions = self.actions.update(event, &mut self.action_state.write().unwrap());
+        self.publish_actions(actions);
+
         if let Some(handlers) = self.handlers.get_mut(&event.into()) {
             for handler in handlers {
                 handler.send(event);
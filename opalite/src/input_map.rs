@@ -0,0 +1,381 @@
+use std::{ collections::{ BTreeMap, HashMap, HashSet }, fs, path::PathBuf };
+use failure::Error;
+use ron;
+use winit::{ ElementState, MouseScrollDelta };
+use crate::{ virtual_key_from_name, InputEvent, Message, ReplayButton, Resources, RLock };
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binding {
+    // named like the `Debug` output of `VirtualKeyCode`, e.g. "W" or "LControl"
+    Key(String),
+    Mouse(ReplayButton),
+    // held when every binding in it is held
+    Chord(Vec<Binding>),
+}
+
+impl Binding {
+    // every key name has to be one `virtual_key_from_name` knows
+    fn validate(&self) -> Result<(), Error> {
+        match self {
+            Binding::Key(name) => if virtual_key_from_name(name).is_none() {
+                bail!("Unknown key '{}'.", name);
+            },
+            Binding::Mouse(_) => (),
+            Binding::Chord(bindings) => for binding in bindings {
+                binding.validate()?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AxisBinding {
+    // 1.0 while `positive` is held, -1.0 while `negative` is
+    Buttons { positive: Binding, negative: Binding },
+    // the lines scrolled during the tick
+    Scroll,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct InputMap {
+    #[serde(default)]
+    pub actions: BTreeMap<String, Vec<Binding>>,
+    #[serde(default)]
+    pub axes: BTreeMap<String, Vec<AxisBinding>>,
+}
+
+impl InputMap {
+    pub fn new() -> Self {
+        InputMap::default()
+    }
+
+    pub fn load(path: &PathBuf, resources: &RLock<Resources>) -> Result<Self, Error> {
+        let resources = resources.read().unwrap();
+        let desc = resources.get_string(path)?;
+        let input_map = ron::de::from_str::<InputMap>(&desc)?;
+        input_map.validate()?;
+        Ok(input_map)
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        for bindings in self.actions.values() {
+            for binding in bindings {
+                binding.validate()?;
+            }
+        }
+
+        for bindings in self.axes.values() {
+            for binding in bindings {
+                if let AxisBinding::Buttons { positive, negative } = binding {
+                    positive.validate()?;
+                    negative.validate()?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // written to the file system rather than the resources, so rebound controls can be kept
+    pub fn save(&self, path: &PathBuf) -> Result<(), Error> {
+        let file = ron::ser::to_string(self)?;
+        fs::write(path, file)?;
+        Ok(())
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map(|bindings| &bindings[..]).unwrap_or(&[])
+    }
+
+    pub fn bind<S: Into<String>>(&mut self, action: S, binding: Binding) {
+        self.actions.entry(action.into()).or_insert_with(|| vec![]).push(binding);
+    }
+
+    // replaces every binding of `action`
+    pub fn rebind<S: Into<String>>(&mut self, action: S, bindings: Vec<Binding>) {
+        self.actions.insert(action.into(), bindings);
+    }
+
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    pub fn bind_axis<S: Into<String>>(&mut self, axis: S, binding: AxisBinding) {
+        self.axes.entry(axis.into()).or_insert_with(|| vec![]).push(binding);
+    }
+
+    pub fn rebind_axis<S: Into<String>>(&mut self, axis: S, bindings: Vec<AxisBinding>) {
+        self.axes.insert(axis.into(), bindings);
+    }
+}
+
+// published on the `EventBus` when an action starts or stops being held, or an axis changes
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Pressed(String),
+    Released(String),
+    Axis(String, f32),
+}
+
+impl Message for Action { }
+
+#[derive(Clone, Debug, Default)]
+pub struct ActionState {
+    pressed: HashSet<String>,
+    just_pressed: HashSet<String>,
+    just_released: HashSet<String>,
+    axes: HashMap<String, f32>,
+}
+
+impl ActionState {
+    pub fn new() -> Self {
+        ActionState::default()
+    }
+
+    pub fn is_pressed(&self, action: &str) -> bool {
+        self.pressed.contains(action)
+    }
+
+    // since the last tick
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.just_pressed.contains(action)
+    }
+
+    pub fn just_released(&self, action: &str) -> bool {
+        self.just_released.contains(action)
+    }
+
+    pub fn axis(&self, axis: &str) -> f32 {
+        self.axes.get(axis).cloned().unwrap_or(0.0)
+    }
+}
+
+// turns `InputEvent`s into actions, owned by `InputEventHandler`
+pub(crate) struct ActionMapper {
+    pub(crate) input_map: InputMap,
+    held: HashSet<Binding>,
+    scroll: f32,
+}
+
+impl ActionMapper {
+    pub(crate) fn new(input_map: InputMap) -> Self {
+        Self {
+            input_map,
+            held: HashSet::new(),
+            scroll: 0.0,
+        }
+    }
+
+    fn is_held(&self, binding: &Binding) -> bool {
+        match binding {
+            Binding::Chord(bindings) => !bindings.is_empty() && bindings.iter().all(|binding| self.is_held(binding)),
+            binding => self.held.contains(binding),
+        }
+    }
+
+    fn axis_value(&self, bindings: &[AxisBinding]) -> f32 {
+        bindings.iter()
+            .map(|binding| match binding {
+                AxisBinding::Buttons { positive, negative } => {
+                    let positive = if self.is_held(positive) { 1.0 } else { 0.0 };
+                    let negative = if self.is_held(negative) { 1.0 } else { 0.0 };
+                    positive - negative
+                },
+                AxisBinding::Scroll => self.scroll,
+            })
+            .sum()
+    }
+
+    // returns the actions that changed
+    pub(crate) fn update(&mut self, event: InputEvent, action_state: &mut ActionState) -> Vec<Action> {
+        let (binding, pressed) = match event {
+            InputEvent::Keyboard { state, virtual_keycode: Some(key), .. } => (Some(Binding::Key(format!("{:?}", key))), state == ElementState::Pressed),
+            InputEvent::MouseClicked { state, button } => (Some(Binding::Mouse(button.into())), state == ElementState::Pressed),
+            InputEvent::MouseScrolled { delta } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => y,
+                    // roughly one line
+                    MouseScrollDelta::PixelDelta(_, y) => y / 20.0,
+                };
+                (None, false)
+            },
+            // releases aren't seen while the window is unfocused, so nothing stays held
+            InputEvent::Focused { focused: false } => {
+                self.held.clear();
+                (None, false)
+            },
+            _ => return vec![],
+        };
+
+        if let Some(binding) = binding {
+            if pressed {
+                self.held.insert(binding);
+            } else {
+                self.held.remove(&binding);
+            }
+        }
+
+        self.refresh(action_state)
+    }
+
+    // the axes driven by scrolling go back to 0 after each tick
+    pub(crate) fn end_tick(&mut self, state: &mut ActionState) -> Vec<Action> {
+        state.just_pressed.clear();
+        state.just_released.clear();
+        self.scroll = 0.0;
+
+        self.refresh(state)
+    }
+
+    fn refresh(&self, state: &mut ActionState) -> Vec<Action> {
+        let mut changed = vec![];
+
+        for (action, bindings) in &self.input_map.actions {
+            let held = bindings.iter().any(|binding| self.is_held(binding));
+
+            if held && !state.pressed.contains(action) {
+                state.pressed.insert(action.clone());
+                state.just_pressed.insert(action.clone());
+                changed.push(Action::Pressed(action.clone()));
+            } else if !held && state.pressed.contains(action) {
+                state.pressed.remove(action);
+                state.just_released.insert(action.clone());
+                changed.push(Action::Released(action.clone()));
+            }
+        }
+
+        // actions that were unbound while held
+        let unbound = state.pressed.iter()
+            .filter(|action| !self.input_map.actions.contains_key(*action))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for action in unbound {
+            state.pressed.remove(&action);
+            state.just_released.insert(action.clone());
+            changed.push(Action::Released(action));
+        }
+
+        for (axis, bindings) in &self.input_map.axes {
+            let value = self.axis_value(bindings);
+
+            if state.axis(axis) != value {
+                state.axes.insert(axis.clone(), value);
+                changed.push(Action::Axis(axis.clone(), value));
+            }
+        }
+
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::{ ModifiersState, MouseButton, VirtualKeyCode };
+    use super::*;
+
+    fn key(key: VirtualKeyCode, state: ElementState) -> InputEvent {
+        InputEvent::Keyboard {
+            state,
+            scancode: 0,
+            virtual_keycode: Some(key),
+            modifiers: ModifiersState::default(),
+        }
+    }
+
+    fn mapper() -> ActionMapper {
+        let mut input_map = InputMap::new();
+        input_map.bind("jump", Binding::Key("Space".to_owned()));
+        input_map.bind("jump", Binding::Mouse(MouseButton::Left.into()));
+        input_map.bind("save", Binding::Chord(vec![Binding::Key("LControl".to_owned()), Binding::Key("S".to_owned())]));
+        input_map.bind_axis("horizontal", AxisBinding::Buttons {
+            positive: Binding::Key("D".to_owned()),
+            negative: Binding::Key("A".to_owned()),
+        });
+        input_map.bind_axis("zoom", AxisBinding::Scroll);
+
+        ActionMapper::new(input_map)
+    }
+
+    #[test]
+    fn presses_and_releases_actions() {
+        let mut mapper = mapper();
+        let mut state = ActionState::new();
+
+        assert_eq!(mapper.update(key(VirtualKeyCode::Space, ElementState::Pressed), &mut state), vec![Action::Pressed("jump".to_owned())]);
+        assert!(state.is_pressed("jump") && state.just_pressed("jump"));
+
+        // a second binding of a held action doesn't press it again
+        let click = InputEvent::MouseClicked { state: ElementState::Pressed, button: MouseButton::Left };
+        assert_eq!(mapper.update(click, &mut state), vec![]);
+
+        mapper.end_tick(&mut state);
+        assert!(state.is_pressed("jump") && !state.just_pressed("jump"));
+
+        assert_eq!(mapper.update(key(VirtualKeyCode::Space, ElementState::Released), &mut state), vec![]);
+        let release = InputEvent::MouseClicked { state: ElementState::Released, button: MouseButton::Left };
+        assert_eq!(mapper.update(release, &mut state), vec![Action::Released("jump".to_owned())]);
+        assert!(!state.is_pressed("jump") && state.just_released("jump"));
+    }
+
+    #[test]
+    fn chords_need_every_binding() {
+        let mut mapper = mapper();
+        let mut state = ActionState::new();
+
+        mapper.update(key(VirtualKeyCode::S, ElementState::Pressed), &mut state);
+        assert!(!state.is_pressed("save"));
+
+        mapper.update(key(VirtualKeyCode::LControl, ElementState::Pressed), &mut state);
+        assert!(state.is_pressed("save"));
+    }
+
+    #[test]
+    fn axes_follow_buttons_and_scrolling() {
+        let mut mapper = mapper();
+        let mut state = ActionState::new();
+
+        mapper.update(key(VirtualKeyCode::D, ElementState::Pressed), &mut state);
+        assert_eq!(state.axis("horizontal"), 1.0);
+
+        mapper.update(key(VirtualKeyCode::A, ElementState::Pressed), &mut state);
+        assert_eq!(state.axis("horizontal"), 0.0);
+
+        mapper.update(InputEvent::MouseScrolled { delta: MouseScrollDelta::LineDelta(0.0, 2.0) }, &mut state);
+        assert_eq!(state.axis("zoom"), 2.0);
+
+        assert_eq!(mapper.end_tick(&mut state), vec![Action::Axis("zoom".to_owned(), 0.0)]);
+    }
+
+    #[test]
+    fn losing_focus_releases_everything() {
+        let mut mapper = mapper();
+        let mut state = ActionState::new();
+
+        mapper.update(key(VirtualKeyCode::Space, ElementState::Pressed), &mut state);
+        mapper.update(key(VirtualKeyCode::D, ElementState::Pressed), &mut state);
+
+        let changed = mapper.update(InputEvent::Focused { focused: false }, &mut state);
+
+        assert_eq!(changed, vec![Action::Released("jump".to_owned()), Action::Axis("horizontal".to_owned(), 0.0)]);
+        assert!(!state.is_pressed("jump"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let mut input_map = InputMap::new();
+        input_map.bind("save", Binding::Chord(vec![Binding::Key("LControl".to_owned()), Binding::Key("Ess".to_owned())]));
+        assert!(input_map.validate().is_err());
+
+        let mut input_map = InputMap::new();
+        input_map.bind_axis("horizontal", AxisBinding::Buttons {
+            positive: Binding::Key("D".to_owned()),
+            negative: Binding::Key("a".to_owned()),
+        });
+        assert!(input_map.validate().is_err());
+
+        assert!(mapper().input_map.validate().is_ok());
+    }
+}
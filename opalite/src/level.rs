@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+use cgmath::Vector3;
+use failure::Error;
+use ron;
+use specs::{ Entity, Join, World };
+use crate::{
+    Camera,
+    Config,
+    InitialPosition,
+    Light,
+    MapMessage,
+    MapTopology,
+    MessageSender,
+    ModelData,
+    Position,
+    PrefabDesc,
+    Resources,
+    RLock,
+    Terrain,
+};
+use crate::prefab::ResolvedPrefab;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelEntity {
+    pub prefab: String,
+    pub position: (i32, i32, i32),
+    #[serde(default)]
+    pub overrides: PrefabDesc,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelLight {
+    pub position: (i32, i32, i32),
+    pub light: Light,
+    #[serde(default)]
+    pub model_data: ModelData,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LevelCamera {
+    pub position: (f32, f32, f32),
+    pub direction: (f32, f32, f32),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LevelDesc {
+    pub map_dimensions: (i32, i32, i32),
+    // `Config::map_topology` if left out
+    #[serde(default)]
+    pub map_topology: Option<MapTopology>,
+    #[serde(default)]
+    pub terrain: Option<PathBuf>,
+    #[serde(default)]
+    pub camera: Option<LevelCamera>,
+    #[serde(default)]
+    pub lights: Vec<LevelLight>,
+    #[serde(default)]
+    pub entities: Vec<LevelEntity>,
+}
+
+impl LevelDesc {
+    pub fn load(path: &PathBuf, resources: &RLock<Resources>) -> Result<Self, Error> {
+        let resources = resources.read().unwrap();
+        let desc = resources.get_string(path)?;
+        Ok(ron::de::from_str::<LevelDesc>(&desc)?)
+    }
+}
+
+// deletes every entity with an `InitialPosition` or a `Position`, resets the map and
+// creates the level's entities, which are placed the next time `MapSystem` runs.
+// The terrain and prefabs are loaded first, so nothing is deleted if any of them fail
+pub fn load_level(world: &mut World, level: LevelDesc) -> Result<Vec<Entity>, Error> {
+    let terrain = match &level.terrain {
+        Some(terrain) => {
+            let resources = world.read_resource::<RLock<Resources>>().clone();
+            Terrain::load(terrain, &resources)?
+        },
+        None => Terrain::default(),
+    };
+
+    let mut prefabs = vec![];
+
+    for LevelEntity { prefab, position, overrides } in level.entities {
+        prefabs.push((ResolvedPrefab::new(world, &prefab, overrides)?, position));
+    }
+
+    let old = {
+        let entities = world.entities();
+        let initial_positions = world.read::<InitialPosition>();
+        let positions = world.read::<Position>();
+
+        entities.join()
+            .filter(|entity| initial_positions.get(*entity).is_some() || positions.get(*entity).is_some())
+            .collect::<Vec<_>>()
+    };
+
+    world.delete_entities(&old[..])
+        .map_err(|err| format_err!("{:?}", err))?;
+    world.maintain();
+
+    let topology = level.map_topology.unwrap_or(world.read_resource::<Config>().map_topology);
+    world.write_resource::<MessageSender<MapMessage>>().send(MapMessage::Reset {
+        dimensions: level.map_dimensions.into(),
+        topology,
+        terrain,
+    });
+
+    if let Some(LevelCamera { position, direction }) = level.camera {
+        let mut camera = world.write_resource::<Camera>();
+        camera.position = position.into();
+        camera.direction = direction.into();
+    }
+
+    let mut created = vec![];
+
+    for LevelLight { position, light, model_data } in level.lights {
+        let position: Vector3<i32> = position.into();
+
+        created.push(world.create_entity()
+            .with(InitialPosition(position))
+            .with(light)
+            .with(model_data)
+            .build());
+    }
+
+    for (prefab, position) in prefabs {
+        created.push(prefab.spawn(world, position.into()));
+    }
+
+    Ok(created)
+}
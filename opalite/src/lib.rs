@@ -44,13 +44,20 @@ mod ai;
 mod config;
 #[macro_use] pub mod gluon_api;
 mod input_events;
+mod input_map;
+mod level;
 mod map;
 mod mutex_ext;
 mod opal;
 mod picker;
+mod prefab;
 pub mod renderer;
+mod replay;
 mod resources;
+mod save;
 mod system;
+mod time;
+mod turns;
 
 pub use back::Backend;
 
@@ -58,7 +65,16 @@ pub use ai::{
     AiComponent,
     AiGoalDo,
     AiGoal,
+    AiGoalFailure,
+    AiMessages,
+    AiScript,
     AiSystem,
+    Behaviour,
+    BehaviourStatus,
+    BehaviourTree,
+    Condition,
+    GoalDesc,
+    TargetSelector,
 };
 
 pub use config::{
@@ -71,43 +87,84 @@ pub use gluon_api::{
 };
 
 pub use input_events::{
+    virtual_key_from_name,
     InputEvent,
     InputEventType,
     InputEventHandler,
 };
 
+pub use input_map::{
+    Action,
+    ActionState,
+    AxisBinding,
+    Binding,
+    InputMap,
+};
+
+pub use level::{
+    load_level,
+    LevelCamera,
+    LevelDesc,
+    LevelEntity,
+    LevelLight,
+};
+
 pub use map::{
     CollisionLayer,
     CollisionLayers,
     InitialPosition,
+    LayerFilter,
     Position,
     Map,
+    MapEvent,
     MapMessage,
     MapSystem,
+    MapTopology,
+    MoveRejection,
+    MAX_DEFERRED_MOVES,
+    PathError,
+    Placement,
+    PlacementPolicy,
+    SightBlockers,
+    Terrain,
+    TerrainDesc,
+    TerrainId,
+    TerrainType,
+    Tile,
+    Viewshed,
+    VisibilitySystem,
 };
 
 pub use mutex_ext::{ RLock, WLock };
 
 pub use opal::{
+    HeadlessOpal,
     OpalBuilder,
     Opal,
     OpalUi,
+    UiCapture,
     WindowClosed,
 };
 
 pub use picker::{
+    PickEvent,
     PickerSystem,
+    Selection,
 };
 
 pub use renderer::{
     Buffer,
     BufferData,
     Camera,
+    Easing,
+    Interpolation,
+    InterpolationSystem,
     Renderer,
     Light,
     LightType,
     MaterialDesc,
     Model,
+    ModelBounds,
     ModelData,
     ModelKey,
     ModelType,
@@ -117,13 +174,52 @@ pub use renderer::{
     Vertex,
 };
 
+pub use replay::{
+    ReplayButton,
+    ReplayFile,
+    ReplayHandle,
+    ReplayInput,
+    ReplayMapMessage,
+    ReplayModifiers,
+    ReplayTick,
+    REPLAY_VERSION,
+};
+
+pub use prefab::{
+    spawn_prefab,
+    spawn_prefab_with,
+    PrefabDesc,
+    PrefabLibrary,
+    PrefabSpawner,
+};
+
 pub use resources::Resources;
 
+pub use save::{
+    SavedEntity,
+    SavedModel,
+    SaveFile,
+    SaveRegistry,
+    SAVE_VERSION,
+};
+
+pub use time::Time;
+
+pub use turns::{
+    Initiative,
+    TurnScheduler,
+    TurnSystem,
+    TURN_COST,
+};
+
 pub use system::{
+    EventBus,
+    EventReader,
     Message,
     MessageIter,
     MessageQueue,
     MessageReceiver,
     MessageSender,
     Shard,
+    EVENT_LIFETIME,
 };
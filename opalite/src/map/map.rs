@@ -0,0 +1,571 @@
+use std::{ collections::{ HashMap, HashSet }, ops, sync::mpsc };
+use cgmath::Vector3;
+use specs::{ Entities, Entity, Fetch, FetchMut, System, ReadStorage, WriteStorage };
+use super::{ MapTopology, Placement, PlacementPolicy, Terrain };
+use crate::{
+    EventBus,
+    Message,
+    MessageQueue,
+    MessageSender,
+    MessageReceiver,
+    Shard,
+    ReplayHandle,
+    RLock,
+    TurnScheduler,
+    WLock,
+};
+
+#[derive(Serialize, Deserialize, Component, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InitialPosition(pub Vector3<i32>);
+
+impl InitialPosition {
+    pub fn x(&self) -> f64 {
+        self.0.x as f64
+    }
+
+    pub fn y(&self) -> f64 {
+        self.0.y as f64
+    }
+
+    pub fn z(&self) -> f64 {
+        self.0.z as f64
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CollisionLayer(pub i32);
+
+impl CollisionLayer {
+    pub const PLAYER: Self = CollisionLayer(1);
+}
+
+#[derive(Serialize, Deserialize, Component, Clone, Debug, PartialEq, Eq)]
+pub struct CollisionLayers(pub HashSet<CollisionLayer>);
+
+impl CollisionLayers {
+    pub fn new<'a>(layers: impl Iterator<Item = &'a CollisionLayer>) -> Self {
+        CollisionLayers(layers.map(|l| *l).collect())
+    }
+}
+
+impl ops::Deref for CollisionLayers {
+    type Target = HashSet<CollisionLayer>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl From<Vector3<i32>> for InitialPosition {
+    fn from(vec: Vector3<i32>) -> InitialPosition {
+        let vec = vec.into();
+        InitialPosition(vec)
+    }
+}
+
+#[derive(Component, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Position;
+
+#[derive(Clone)]
+pub enum MapMessage {
+    Move { entity: Entity, new_location: Vector3<i32>, absolute: bool, reply: Option<mpsc::SyncSender<bool>> },
+    // puts the entity at `location` without checking for collisions, e.g. when loading a save
+    Place { entity: Entity, location: Vector3<i32> },
+    // replaces the map with an empty one, messages sent before it are dropped
+    Reset { dimensions: Vector3<i32>, topology: MapTopology, terrain: Terrain },
+}
+
+impl Message for MapMessage { }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MoveRejection {
+    OutOfBounds,
+    Blocked,
+    // the entity already had `MAX_DEFERRED_MOVES` waiting for its turn
+    TooManyDeferred,
+}
+
+// how many moves a scheduled entity can send outside of its turn
+pub const MAX_DEFERRED_MOVES: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MapEvent {
+    EntityPlaced { entity: Entity, location: Vector3<i32> },
+    EntityMoved { entity: Entity, from: Vector3<i32>, to: Vector3<i32> },
+    MoveRejected { entity: Entity, from: Option<Vector3<i32>>, to: Vector3<i32>, reason: MoveRejection },
+    EntityRemoved { entity: Entity, location: Vector3<i32> },
+    PlacementFailed { entity: Entity, location: Vector3<i32> },
+    MapReset { dimensions: Vector3<i32> },
+}
+
+impl Message for MapEvent { }
+
+// `z / 2` rounded down, so rows before the first one are offset like the rest
+fn half_row(z: i32) -> i32 {
+    if z < 0 { (z - 1) / 2 } else { z / 2 }
+}
+
+#[derive(Debug, Clone)]
+pub struct Map {
+    width: i32,
+    height: i32,
+    depth: i32,
+    topology: MapTopology,
+    terrain: Terrain,
+    cells: HashMap<Vector3<i32>, HashSet<Entity>>,
+    entities: HashMap<Entity, Vector3<i32>>,
+    // where each entity was before the last tick, for drawing between ticks
+    previous: HashMap<Entity, Vector3<i32>>,
+}
+
+impl Map {
+    pub fn new(width: i32, depth: i32, height: i32, topology: MapTopology) -> Self {
+        Self {
+            width,
+            depth,
+            height,
+            topology,
+            terrain: Terrain::default(),
+            cells: HashMap::new(),
+            entities: HashMap::new(),
+            previous: HashMap::new(),
+        }
+    }
+
+    pub fn in_bounds(&self, location: &Vector3<i32>) -> bool {
+        let column = self.column(location);
+
+        column >= 0 && column <= self.width &&
+        location.y >= 0 && location.y <= self.height &&
+        location.z >= 0 && location.z <= self.depth
+    }
+
+    // hex maps are bounded as a rectangle of offset rows rather than in axial coordinates,
+    // so the column of a hex cell is `q + r / 2`
+    pub fn column(&self, location: &Vector3<i32>) -> i32 {
+        match self.topology {
+            MapTopology::Hex => location.x + half_row(location.z),
+            MapTopology::Square4 | MapTopology::Square8 => location.x,
+        }
+    }
+
+    // the cell in `column` of row `z`, the inverse of `column`
+    pub fn from_column(&self, column: i32, y: i32, z: i32) -> Vector3<i32> {
+        match self.topology {
+            MapTopology::Hex => Vector3::new(column - half_row(z), y, z),
+            MapTopology::Square4 | MapTopology::Square8 => Vector3::new(column, y, z),
+        }
+    }
+
+    pub fn dimensions(&self) -> Vector3<i32> {
+        Vector3::new(self.width, self.height, self.depth)
+    }
+
+    pub fn topology(&self) -> MapTopology {
+        self.topology
+    }
+
+    pub fn terrain(&self) -> &Terrain {
+        &self.terrain
+    }
+
+    pub fn terrain_mut(&mut self) -> &mut Terrain {
+        &mut self.terrain
+    }
+
+    pub fn set_terrain(&mut self, terrain: Terrain) {
+        self.terrain = terrain;
+    }
+
+    pub fn neighbours(&self, location: &Vector3<i32>) -> Vec<Vector3<i32>> {
+        self.topology.directions().iter()
+            .chain(self.topology.vertical_directions())
+            .map(|direction| *location + *direction)
+            .filter(|neighbour| self.in_bounds(neighbour))
+            .collect()
+    }
+
+    pub fn distance(&self, a: &Vector3<i32>, b: &Vector3<i32>) -> i32 {
+        self.topology.distance(a, b)
+    }
+
+    pub fn world_position(&self, location: &Vector3<i32>) -> Vector3<f32> {
+        let mut position = self.topology.to_world(location);
+        position.y += self.terrain.height(location);
+        position
+    }
+
+    pub fn location(&self, entity: &Entity) -> Option<&Vector3<i32>> {
+        self.entities.get(entity)
+    }
+
+    pub fn entities(&self, location: &Vector3<i32>) -> Option<impl Iterator<Item = &Entity>> {
+        if let Some(entities) = self.cells.get(location) {
+            if entities.len() == 0 {
+                None
+            } else {
+                Some(entities.iter())
+            }
+        } else {
+            None
+        }
+    }
+
+    // where the entity was before the last tick
+    pub fn previous_location(&self, entity: &Entity) -> Option<&Vector3<i32>> {
+        self.previous.get(entity)
+    }
+
+    pub(crate) fn start_tick(&mut self) {
+        self.previous = self.entities.clone();
+    }
+
+    pub fn located(&self) -> impl Iterator<Item = (&Entity, &Vector3<i32>)> {
+        self.entities.iter()
+    }
+
+    // the same for any two maps with the same entities in the same places
+    pub fn checksum(&self) -> u64 {
+        let mut located = self.entities.iter()
+            .map(|(entity, location)| (entity.id(), entity.gen().id(), location.x, location.y, location.z))
+            .collect::<Vec<_>>();
+        located.sort();
+
+        // FNV-1a, so that the result doesn't change between builds
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for (id, gen, x, y, z) in located {
+            for value in &[id as u64, gen as u64, x as u64, y as u64, z as u64] {
+                for byte in 0..8 {
+                    hash ^= (value >> (byte * 8)) & 0xff;
+                    hash = hash.wrapping_mul(0x0100_0000_01b3);
+                }
+            }
+        }
+        hash
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    // like `can_move`, but a diagonal step on a square map also needs both cells beside it
+    // to be free so that it can't cut the corner of a wall
+    pub fn can_step<'a>(&self, entity: Entity, from: Vector3<i32>, to: Vector3<i32>, collision_layers: &ReadStorage<'a, CollisionLayers>) -> bool {
+        if !self.can_move(entity, to, collision_layers) {
+            return false;
+        }
+
+        let step = to - from;
+        let is_diagonal = self.topology != MapTopology::Hex &&
+            step.y == 0 && step.x.abs() == 1 && step.z.abs() == 1;
+
+        !is_diagonal || (
+            self.can_move(entity, Vector3::new(to.x, from.y, from.z), collision_layers) &&
+            self.can_move(entity, Vector3::new(from.x, from.y, to.z), collision_layers)
+        )
+    }
+
+    pub fn can_move<'a>(&self, entity: Entity, location: Vector3<i32>, collision_layers: &ReadStorage<'a, CollisionLayers>) -> bool {
+        if !self.in_bounds(&location) || !self.terrain.is_walkable(&location) {
+            return false;
+        }
+
+        let collisions_to_check = match collision_layers.get(entity) {
+            Some(layers) => layers,
+            // if there aren't any collision layers then nothing else blocks you
+            None => return true,
+        };
+
+        if let Some(entities) = self.entities(&location) {
+            for entity in entities {
+                if let Some(collisions) = collision_layers.get(*entity) {
+                    for collision in collisions.iter() {
+                        if collisions_to_check.contains(collision) {
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    pub fn move_entity(&mut self, entity: Entity, location: Vector3<i32>) {
+        self.remove_entity(&entity);
+
+        self.entities.insert(entity, location);
+        self.cells.entry(location)
+            .or_insert_with(HashSet::new)
+            .insert(entity);
+    }
+
+    pub fn remove_entity(&mut self, entity: &Entity) -> Option<Vector3<i32>> {
+        let location = self.entities.remove(entity)?;
+
+        let is_empty = match self.cells.get_mut(&location) {
+            Some(cell) => {
+                cell.remove(entity);
+                cell.is_empty()
+            },
+            None => false,
+        };
+
+        if is_empty {
+            self.cells.remove(&location);
+        }
+
+        Some(location)
+    }
+}
+
+pub struct MapSystem {
+    receiver: MessageReceiver<MapMessage>,
+    sender: MessageSender<MapMessage>,
+    map: WLock<Map>,
+    placement_policy: PlacementPolicy,
+    failed_placements: HashSet<Entity>,
+    // moves from scheduled entities that were sent outside of their turn
+    deferred: Vec<MapMessage>,
+}
+
+impl MapSystem {
+    pub fn new(map_size: Vector3<i32>, topology: MapTopology) -> Self {
+        let (sender, receiver) = MessageQueue::new();
+
+        let Vector3 { x: width, y: depth, z: height } = map_size;
+
+        let map = WLock::new(Map::new(width, depth, height, topology));
+
+        Self {
+            sender,
+            receiver,
+            map,
+            placement_policy: PlacementPolicy::default(),
+            failed_placements: HashSet::new(),
+            deferred: vec![],
+        }
+    }
+
+    pub fn map(&self) -> RLock<Map> {
+        self.map.get_reader()
+    }
+
+    pub fn set_terrain(&mut self, terrain: Terrain) {
+        self.map.write().unwrap().set_terrain(terrain);
+    }
+
+    pub fn set_placement_policy(&mut self, placement_policy: PlacementPolicy) {
+        self.placement_policy = placement_policy;
+    }
+}
+
+impl<'a> Shard<'a> for MapSystem {
+    type Message = MapMessage;
+
+    fn sender(&self) -> MessageSender<Self::Message> {
+        self.sender.clone()
+    }
+}
+
+impl<'a> System<'a> for MapSystem {
+    type SystemData = (Entities<'a>, ReadStorage<'a, InitialPosition>, ReadStorage<'a, Placement>, ReadStorage<'a, CollisionLayers>, WriteStorage<'a, Position>, FetchMut<'a, TurnScheduler>, Fetch<'a, ReplayHandle>, Fetch<'a, EventBus>);
+
+    fn run(&mut self, (entities, initial_positions, placements, collision_layers, mut positions, mut scheduler, replay, events): Self::SystemData) {
+        use specs::Join;
+
+        let mut map = self.map.write().unwrap();
+        map.start_tick();
+
+        let mut received = self.receiver.messages().collect::<Vec<_>>();
+
+        let reset = received.iter().rposition(|message| match message {
+            MapMessage::Reset { .. } => true,
+            _ => false,
+        });
+
+        if let Some(reset) = reset {
+            let message = received.remove(reset);
+            replay.record_map_message(&message);
+
+            if let MapMessage::Reset { dimensions, topology, terrain } = message {
+                let Vector3 { x: width, y: depth, z: height } = dimensions;
+                *map = Map::new(width, depth, height, topology);
+                map.set_terrain(terrain);
+
+                events.publish(MapEvent::MapReset { dimensions });
+            }
+
+            received.drain(..reset);
+            self.deferred.clear();
+            self.failed_placements.clear();
+        }
+
+        // deleted entities, or ones that have had their `Position` removed
+        let removed: Vec<_> = map.located()
+            .filter(|(entity, _)| !entities.is_alive(**entity) || positions.get(**entity).is_none())
+            .map(|(entity, location)| (*entity, *location))
+            .collect();
+
+        for (entity, location) in removed {
+            map.remove_entity(&entity);
+            events.publish(MapEvent::EntityRemoved { entity, location });
+        }
+
+        self.failed_placements.retain(|entity| entities.is_alive(*entity));
+
+        let mut entities_to_add_position = vec![];
+        for (entity, initial_position, _) in (&*entities, &initial_positions, !&positions).join() {
+            if self.failed_placements.contains(&entity) {
+                continue;
+            }
+
+            let requested = initial_position.0;
+            let policy = placements.get(entity)
+                .map(|placement| placement.0)
+                .unwrap_or(self.placement_policy);
+
+            let location = if map.in_bounds(&requested) && map.can_move(entity, requested, &collision_layers) {
+                Some(requested)
+            } else {
+                match policy {
+                    PlacementPolicy::Nearest => map.nearest_free_cell(entity, &requested, &collision_layers),
+                    PlacementPolicy::Fail => None,
+                    // entities can overlap, but they can't leave the map
+                    PlacementPolicy::Force if map.in_bounds(&requested) => Some(requested),
+                    PlacementPolicy::Force => None,
+                }
+            };
+
+            match location {
+                Some(location) => {
+                    map.move_entity(entity, location);
+                    entities_to_add_position.push(entity);
+
+                    events.publish(MapEvent::EntityPlaced { entity, location });
+                },
+                None => {
+                    self.failed_placements.insert(entity);
+                    events.publish(MapEvent::PlacementFailed { entity, location: requested });
+                },
+            }
+        }
+
+        for entity in entities_to_add_position {
+            positions.insert(entity, Position);
+        }
+
+        let messages = self.deferred.drain(..)
+            .chain(received)
+            .collect::<Vec<_>>();
+
+        for message in messages {
+            use self::MapMessage::*;
+
+            let actor = match &message {
+                Move { entity, .. } => Some(*entity),
+                Place { .. } | Reset { .. } => None,
+            };
+
+            if let Some(actor) = actor {
+                // moves for deleted entities are dropped whether or not the scheduler is on
+                if !entities.is_alive(actor) {
+                    continue;
+                }
+
+                if !scheduler.is_turn(actor) {
+                    let deferred = self.deferred.iter()
+                        .filter(|deferred| match deferred {
+                            Move { entity, .. } => *entity == actor,
+                            Place { .. } | Reset { .. } => false,
+                        })
+                        .count();
+
+                    if deferred < MAX_DEFERRED_MOVES {
+                        self.deferred.push(message);
+                    } else if let Move { entity, new_location, absolute, reply } = message {
+                        let from = map.location(&entity).cloned();
+                        let to = match (absolute, from) {
+                            (false, Some(from)) => from + new_location,
+                            _ => new_location,
+                        };
+
+                        reply.map(|reply| reply.send(false));
+                        events.publish(MapEvent::MoveRejected { entity, from, to, reason: MoveRejection::TooManyDeferred });
+                    }
+                    continue;
+                }
+            }
+
+            replay.record_map_message(&message);
+
+            match message {
+                Move { entity, new_location, absolute, reply } => {
+                    let previous_location = map.location(&entity).map(|v| *v);
+                    let location = previous_location.unwrap_or(Vector3::new(0, 0, 0));
+
+                    let new_location = if absolute { new_location } else { location + new_location };
+
+                    let rejection = if !map.in_bounds(&new_location) {
+                        Some(MoveRejection::OutOfBounds)
+                    } else if !map.can_step(entity, location, new_location, &collision_layers) {
+                        Some(MoveRejection::Blocked)
+                    } else {
+                        None
+                    };
+
+                    if let Some(reason) = rejection {
+                        reply.map(|reply| reply.send(false));
+                        // a rejected move still uses up the turn, so that the scheduler can't stall
+                        scheduler.end_turn(entity);
+                        events.publish(MapEvent::MoveRejected {
+                            entity,
+                            from: previous_location,
+                            to: new_location,
+                            reason,
+                        });
+                        continue;
+                    }
+
+                    map.move_entity(entity, new_location);
+                    reply.map(|reply| reply.send(true));
+                    scheduler.end_turn(entity);
+
+                    if positions.get(entity).is_none() {
+                        positions.insert(entity, Position);
+                    }
+
+                    events.publish(match previous_location {
+                        Some(from) => MapEvent::EntityMoved { entity, from, to: new_location },
+                        None => MapEvent::EntityPlaced { entity, location: new_location },
+                    });
+                },
+                Place { entity, location } => {
+                    if !entities.is_alive(entity) {
+                        continue;
+                    }
+
+                    let previous_location = map.location(&entity).map(|v| *v);
+                    map.move_entity(entity, location);
+
+                    if positions.get(entity).is_none() {
+                        positions.insert(entity, Position);
+                    }
+
+                    events.publish(match previous_location {
+                        Some(from) => MapEvent::EntityMoved { entity, from, to: location },
+                        None => MapEvent::EntityPlaced { entity, location },
+                    });
+                },
+                // only the last reset is kept, and it's handled before anything else
+                Reset { .. } => (),
+            }
+        }
+
+        replay.check(map.checksum());
+    }
+}
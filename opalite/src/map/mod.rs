@@ -0,0 +1,31 @@
+mod map;
+pub use self::map::{
+    CollisionLayer,
+    CollisionLayers,
+    InitialPosition,
+    Position,
+    Map,
+    MapEvent,
+    MapMessage,
+    MapSystem,
+    MoveRejection,
+    MAX_DEFERRED_MOVES,
+};
+
+mod pathfinding;
+pub use self::pathfinding::PathError;
+
+mod topology;
+pub use self::topology::MapTopology;
+
+mod terrain;
+pub use self::terrain::{ HeightmapDesc, Terrain, TerrainDesc, TerrainId, TerrainType, Tile };
+
+mod visibility;
+pub use self::visibility::{ SightBlockers, Viewshed, VisibilitySystem };
+
+mod query;
+pub use self::query::LayerFilter;
+
+mod placement;
+pub use self::placement::{ Placement, PlacementPolicy };
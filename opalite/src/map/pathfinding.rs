@@ -0,0 +1,202 @@
+use std::{ cmp::Ordering, collections::{ BinaryHeap, HashMap, VecDeque } };
+use cgmath::Vector3;
+use specs::{ Entity, ReadStorage };
+use crate::{ CollisionLayers, Map };
+
+#[derive(Fail, Debug, Clone, PartialEq)]
+pub enum PathError {
+    #[fail(display = "Target {:?} is outside of the map.", _0)]
+    OutOfBounds(Vector3<i32>),
+    #[fail(display = "Target {:?} is blocked.", _0)]
+    TargetBlocked(Vector3<i32>),
+    #[fail(display = "No path found to {:?}.", _0)]
+    NoPath(Vector3<i32>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Node {
+    estimate: i32,
+    cost: i32,
+    location: Vector3<i32>,
+}
+
+impl Ord for Node {
+    // reversed so that the `BinaryHeap` pops the cheapest node first
+    fn cmp(&self, other: &Node) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+            .then_with(|| self.cost.cmp(&other.cost))
+    }
+}
+
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Node) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Map {
+    // returns the cells to step through, not including `start`
+    pub fn find_path<'a>(&self, entity: Entity, start: Vector3<i32>, target: Vector3<i32>, collision_layers: &ReadStorage<'a, CollisionLayers>) -> Result<VecDeque<Vector3<i32>>, PathError> {
+        if !self.in_bounds(&target) {
+            return Err(PathError::OutOfBounds(target));
+        }
+
+        if start == target {
+            return Ok(VecDeque::new());
+        }
+
+        if !self.can_move(entity, target, collision_layers) {
+            return Err(PathError::TargetBlocked(target));
+        }
+
+        let mut open = BinaryHeap::new();
+        let mut came_from = HashMap::new();
+        let mut costs = HashMap::new();
+
+        costs.insert(start, 0);
+        open.push(Node { estimate: self.distance(&start, &target), cost: 0, location: start });
+
+        while let Some(Node { cost, location, .. }) = open.pop() {
+            if location == target {
+                let mut path = VecDeque::new();
+                let mut current = location;
+                while current != start {
+                    path.push_front(current);
+                    current = came_from[&current];
+                }
+                return Ok(path);
+            }
+
+            // a cheaper route to this cell has already been expanded
+            if cost > costs[&location] {
+                continue;
+            }
+
+            for neighbour in self.neighbours(&location) {
+                if !self.can_step(entity, location, neighbour, collision_layers) {
+                    continue;
+                }
+
+                let new_cost = cost + self.terrain().movement_cost(&neighbour) as i32;
+                let is_better = costs.get(&neighbour)
+                    .map(|old_cost| new_cost < *old_cost)
+                    .unwrap_or(true);
+
+                if is_better {
+                    costs.insert(neighbour, new_cost);
+                    came_from.insert(neighbour, location);
+                    open.push(Node {
+                        estimate: new_cost + self.distance(&neighbour, &target),
+                        cost: new_cost,
+                        location: neighbour,
+                    });
+                }
+            }
+        }
+
+        Err(PathError::NoPath(target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use specs::World;
+    use crate::{ CollisionLayer, MapTopology, Terrain, TerrainId, TerrainType, Tile };
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<CollisionLayers>();
+        world
+    }
+
+    fn walled_map(topology: MapTopology, walls: &[(i32, i32)]) -> Map {
+        let mut terrain = Terrain::new(vec![TerrainType {
+            name: "wall".to_owned(),
+            cost: 1,
+            walkable: false,
+            opaque: true,
+        }]);
+
+        for (x, z) in walls {
+            terrain.set(Vector3::new(*x, 0, *z), Tile { terrain: TerrainId(0), height: 0.0 });
+        }
+
+        let mut map = Map::new(5, 5, 0, topology);
+        map.set_terrain(terrain);
+        map
+    }
+
+    #[test]
+    fn finds_straight_path() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+        let map = walled_map(MapTopology::Square4, &[]);
+
+        let path = map.find_path(entity, Vector3::new(0, 0, 0), Vector3::new(3, 0, 0), &world.read()).unwrap();
+
+        assert_eq!(path, vec![
+            Vector3::new(1, 0, 0),
+            Vector3::new(2, 0, 0),
+            Vector3::new(3, 0, 0),
+        ].into_iter().collect::<VecDeque<_>>());
+    }
+
+    #[test]
+    fn routes_around_walls() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+        let walls = [(1, 0), (1, 1), (1, 2), (1, 3)];
+        let map = walled_map(MapTopology::Square4, &walls);
+
+        let path = map.find_path(entity, Vector3::new(0, 0, 0), Vector3::new(2, 0, 0), &world.read()).unwrap();
+
+        assert_eq!(path.len(), 10);
+        assert!(path.iter().all(|cell| map.terrain().is_walkable(cell)));
+        assert_eq!(path.back(), Some(&Vector3::new(2, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_blocked_and_out_of_bounds_targets() {
+        let mut world = world();
+        let layers = CollisionLayers::new([CollisionLayer::PLAYER].iter());
+        let entity = world.create_entity().with(layers.clone()).build();
+        let ghost = world.create_entity().build();
+        let blocker = world.create_entity().with(layers).build();
+
+        let mut map = walled_map(MapTopology::Square4, &[]);
+        let target = Vector3::new(2, 0, 2);
+        map.move_entity(blocker, target);
+
+        let start = Vector3::new(0, 0, 0);
+        assert_eq!(map.find_path(entity, start, target, &world.read()), Err(PathError::TargetBlocked(target)));
+        assert_eq!(map.find_path(ghost, start, target, &world.read()).map(|path| path.len()), Ok(4));
+
+        let outside = Vector3::new(9, 0, 0);
+        assert_eq!(map.find_path(entity, start, outside, &world.read()), Err(PathError::OutOfBounds(outside)));
+    }
+
+    #[test]
+    fn fails_without_a_route() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+        let walls = [(1, 0), (1, 1), (1, 2), (1, 3), (1, 4), (1, 5)];
+        let map = walled_map(MapTopology::Square4, &walls);
+
+        let target = Vector3::new(3, 0, 3);
+        assert_eq!(map.find_path(entity, Vector3::new(0, 0, 0), target, &world.read()), Err(PathError::NoPath(target)));
+    }
+
+    #[test]
+    fn diagonals_dont_cut_corners() {
+        let mut world = world();
+        let entity = world.create_entity().build();
+        let map = walled_map(MapTopology::Square8, &[(1, 0), (0, 1)]);
+
+        let target = Vector3::new(1, 0, 1);
+        assert_eq!(map.find_path(entity, Vector3::new(0, 0, 0), target, &world.read()), Err(PathError::NoPath(target)));
+
+        let open = walled_map(MapTopology::Square8, &[]);
+        assert_eq!(open.find_path(entity, Vector3::new(0, 0, 0), target, &world.read()).map(|path| path.len()), Ok(1));
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::{ HashSet, VecDeque };
+use cgmath::Vector3;
+use specs::{ Entity, ReadStorage };
+use crate::{ CollisionLayers, Map };
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PlacementPolicy {
+    // place the entity in the closest cell it can move into
+    Nearest,
+    // don't place the entity at all
+    Fail,
+    // place the entity even if it overlaps something, as long as it's on the map
+    Force,
+}
+
+impl Default for PlacementPolicy {
+    fn default() -> Self {
+        PlacementPolicy::Nearest
+    }
+}
+
+// overrides `Config::placement_policy` for a single entity
+#[derive(Component, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Placement(pub PlacementPolicy);
+
+impl Map {
+    fn clamp(&self, location: &Vector3<i32>) -> Vector3<i32> {
+        let Vector3 { x: width, y: height, z: depth } = self.dimensions();
+
+        self.from_column(
+            self.column(location).max(0).min(width),
+            location.y.max(0).min(height),
+            location.z.max(0).min(depth),
+        )
+    }
+
+    // searches outward from `location`, ignoring whether the cells in between are blocked
+    pub fn nearest_free_cell<'a>(&self, entity: Entity, location: &Vector3<i32>, collision_layers: &ReadStorage<'a, CollisionLayers>) -> Option<Vector3<i32>> {
+        let start = self.clamp(location);
+
+        let mut open = VecDeque::new();
+        let mut visited = HashSet::new();
+
+        open.push_back(start);
+        visited.insert(start);
+
+        while let Some(cell) = open.pop_front() {
+            if self.can_move(entity, cell, collision_layers) {
+                return Some(cell);
+            }
+
+            for neighbour in self.neighbours(&cell) {
+                if visited.insert(neighbour) {
+                    open.push_back(neighbour);
+                }
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashSet;
+use cgmath::Vector3;
+use specs::{ Entity, ReadStorage };
+use crate::{ CollisionLayer, CollisionLayers, Map };
+
+pub struct LayerFilter<'s, 'a: 's> {
+    pub layers: &'s HashSet<CollisionLayer>,
+    pub collision_layers: &'s ReadStorage<'a, CollisionLayers>,
+}
+
+impl<'s, 'a: 's> LayerFilter<'s, 'a> {
+    pub fn new(layers: &'s HashSet<CollisionLayer>, collision_layers: &'s ReadStorage<'a, CollisionLayers>) -> Self {
+        Self { layers, collision_layers }
+    }
+
+    pub fn allows(&self, entity: &Entity) -> bool {
+        match self.collision_layers.get(*entity) {
+            Some(layers) => layers.iter().any(|layer| self.layers.contains(layer)),
+            None => false,
+        }
+    }
+}
+
+impl Map {
+    fn entities_in_cells(&self, cells: impl Iterator<Item = Vector3<i32>>, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        cells
+            .flat_map(|cell| self.entities(&cell).into_iter().flat_map(|entities| entities))
+            .filter(|entity| filter.map(|filter| filter.allows(entity)).unwrap_or(true))
+            .cloned()
+            .collect()
+    }
+
+    fn entities_where(&self, contains: impl Fn(&Vector3<i32>) -> bool, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        self.located()
+            .filter(|(_, location)| contains(location))
+            .map(|(entity, _)| *entity)
+            .filter(|entity| filter.map(|filter| filter.allows(entity)).unwrap_or(true))
+            .collect()
+    }
+
+    // walks the cells in the box when that's cheaper than checking every entity
+    fn entities_in_bounds(&self, min: Vector3<i32>, max: Vector3<i32>, contains: impl Fn(&Vector3<i32>) -> bool, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        let size = max - min;
+        let volume = (size.x + 1).max(0) as usize * (size.y + 1).max(0) as usize * (size.z + 1).max(0) as usize;
+
+        if volume > self.len() {
+            return self.entities_where(|location| {
+                location.x >= min.x && location.x <= max.x &&
+                location.y >= min.y && location.y <= max.y &&
+                location.z >= min.z && location.z <= max.z &&
+                contains(location)
+            }, filter);
+        }
+
+        let cells = (min.x..max.x + 1)
+            .flat_map(move |x| (min.y..max.y + 1).map(move |y| (x, y)))
+            .flat_map(move |(x, y)| (min.z..max.z + 1).map(move |z| Vector3::new(x, y, z)))
+            .filter(|cell| contains(cell));
+
+        self.entities_in_cells(cells, filter)
+    }
+
+    fn sort_by_distance(&self, center: &Vector3<i32>, mut entities: Vec<Entity>) -> Vec<Entity> {
+        entities.sort_by_key(|entity| self.location(entity).map(|location| self.distance(center, location)));
+        entities
+    }
+
+    // sorted from nearest to furthest
+    pub fn entities_in_radius(&self, center: &Vector3<i32>, radius: i32, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        let extent = Vector3::new(radius, radius, radius);
+        let entities = self.entities_in_bounds(*center - extent, *center + extent, |location| {
+            self.distance(center, location) <= radius
+        }, filter);
+
+        self.sort_by_distance(center, entities)
+    }
+
+    pub fn entities_on_ring(&self, center: &Vector3<i32>, radius: i32, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        let extent = Vector3::new(radius, radius, radius);
+        self.entities_in_bounds(*center - extent, *center + extent, |location| {
+            self.distance(center, location) == radius
+        }, filter)
+    }
+
+    pub fn entities_in_box(&self, min: &Vector3<i32>, max: &Vector3<i32>, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        self.entities_in_bounds(*min, *max, |_| true, filter)
+    }
+
+    // sorted from `from` to `to`
+    pub fn entities_on_line(&self, from: &Vector3<i32>, to: &Vector3<i32>, filter: Option<&LayerFilter>) -> Vec<Entity> {
+        let line = self.topology().line(from, to);
+        self.entities_in_cells(line.into_iter(), filter)
+    }
+
+    pub fn nearest_entity(&self, center: &Vector3<i32>, radius: i32, filter: Option<&LayerFilter>, exclude: Option<Entity>) -> Option<Entity> {
+        self.entities_in_radius(center, radius, filter)
+            .into_iter()
+            .find(|entity| Some(*entity) != exclude)
+    }
+}
@@ -0,0 +1,171 @@
+use std::{ collections::HashMap, path::PathBuf };
+use cgmath::Vector3;
+use failure::Error;
+use image;
+use ron;
+use crate::{ Resources, RLock };
+
+fn default_cost() -> u32 {
+    1
+}
+
+fn default_walkable() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TerrainType {
+    pub name: String,
+    #[serde(default = "default_cost")]
+    pub cost: u32,
+    #[serde(default = "default_walkable")]
+    pub walkable: bool,
+    #[serde(default)]
+    pub opaque: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TerrainId(pub usize);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Tile {
+    pub terrain: TerrainId,
+    pub height: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeightmapDesc {
+    pub path: PathBuf,
+    pub scale: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TerrainDesc {
+    pub types: Vec<TerrainType>,
+    pub legend: HashMap<char, String>,
+    // one list of rows per `y` level, rows go along `z` and characters along `x`
+    pub layers: Vec<Vec<String>>,
+    pub heightmap: Option<HeightmapDesc>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Terrain {
+    types: Vec<TerrainType>,
+    tiles: HashMap<Vector3<i32>, Tile>,
+}
+
+impl Terrain {
+    pub fn new(types: Vec<TerrainType>) -> Self {
+        Self {
+            types,
+            tiles: HashMap::new(),
+        }
+    }
+
+    pub fn load(path: &PathBuf, resources: &RLock<Resources>) -> Result<Self, Error> {
+        let desc = {
+            let resources = resources.read().unwrap();
+            let desc = resources.get_string(path)?;
+            ron::de::from_str::<TerrainDesc>(&desc)?
+        };
+
+        Terrain::from_desc(desc, resources)
+    }
+
+    pub fn from_desc(desc: TerrainDesc, resources: &RLock<Resources>) -> Result<Self, Error> {
+        let TerrainDesc { types, legend, layers, heightmap } = desc;
+        let mut terrain = Terrain::new(types);
+
+        for (y, rows) in layers.iter().enumerate() {
+            for (z, row) in rows.iter().enumerate() {
+                for (x, symbol) in row.chars().enumerate() {
+                    let name = match legend.get(&symbol) {
+                        Some(name) => name,
+                        None => bail!("Terrain symbol '{}' isn't in the legend.", symbol),
+                    };
+
+                    let id = match terrain.find(name) {
+                        Some(id) => id,
+                        None => bail!("Terrain type \"{}\" doesn't exist.", name),
+                    };
+
+                    terrain.set(Vector3::new(x as i32, y as i32, z as i32), Tile {
+                        terrain: id,
+                        height: 0.0,
+                    });
+                }
+            }
+        }
+
+        if let Some(HeightmapDesc { path, scale }) = heightmap {
+            let resources = resources.read().unwrap();
+            let bytes = resources.get(&path)?;
+            let heightmap = image::load_from_memory(&bytes)?.to_luma();
+
+            let (width, depth) = heightmap.dimensions();
+
+            // heights only apply to tiles that are defined in `layers`
+            for (cell, tile) in terrain.tiles.iter_mut() {
+                if cell.x < 0 || cell.z < 0 || cell.x as u32 >= width || cell.z as u32 >= depth {
+                    continue;
+                }
+
+                let pixel = heightmap.get_pixel(cell.x as u32, cell.z as u32);
+                tile.height = (pixel.data[0] as f32 / 255.0) * scale;
+            }
+        }
+
+        Ok(terrain)
+    }
+
+    pub fn find(&self, name: &str) -> Option<TerrainId> {
+        self.types.iter()
+            .position(|ty| ty.name == name)
+            .map(|i| TerrainId(i))
+    }
+
+    pub fn terrain_type(&self, id: TerrainId) -> Option<&TerrainType> {
+        self.types.get(id.0)
+    }
+
+    pub fn tile(&self, location: &Vector3<i32>) -> Option<&Tile> {
+        self.tiles.get(location)
+    }
+
+    pub fn set(&mut self, location: Vector3<i32>, tile: Tile) {
+        self.tiles.insert(location, tile);
+    }
+
+    pub fn remove(&mut self, location: &Vector3<i32>) -> Option<Tile> {
+        self.tiles.remove(location)
+    }
+
+    fn tile_type(&self, location: &Vector3<i32>) -> Option<&TerrainType> {
+        self.tile(location).and_then(|tile| self.terrain_type(tile.terrain))
+    }
+
+    // cells without terrain can be walked on
+    pub fn is_walkable(&self, location: &Vector3<i32>) -> bool {
+        self.tile_type(location)
+            .map(|ty| ty.walkable)
+            .unwrap_or(true)
+    }
+
+    pub fn is_opaque(&self, location: &Vector3<i32>) -> bool {
+        self.tile_type(location)
+            .map(|ty| ty.opaque)
+            .unwrap_or(false)
+    }
+
+    pub fn movement_cost(&self, location: &Vector3<i32>) -> u32 {
+        self.tile_type(location)
+            .map(|ty| ty.cost.max(1))
+            .unwrap_or(1)
+    }
+
+    pub fn height(&self, location: &Vector3<i32>) -> f32 {
+        self.tile(location)
+            .map(|tile| tile.height)
+            .unwrap_or(0.0)
+    }
+}
@@ -0,0 +1,170 @@
+use cgmath::Vector3;
+
+const SQUARE_4: [Vector3<i32>; 4] = [
+    Vector3 { x: 1, y: 0, z: 0 },
+    Vector3 { x: -1, y: 0, z: 0 },
+    Vector3 { x: 0, y: 0, z: 1 },
+    Vector3 { x: 0, y: 0, z: -1 },
+];
+
+const SQUARE_8: [Vector3<i32>; 8] = [
+    Vector3 { x: 1, y: 0, z: 0 },
+    Vector3 { x: -1, y: 0, z: 0 },
+    Vector3 { x: 0, y: 0, z: 1 },
+    Vector3 { x: 0, y: 0, z: -1 },
+    Vector3 { x: 1, y: 0, z: 1 },
+    Vector3 { x: 1, y: 0, z: -1 },
+    Vector3 { x: -1, y: 0, z: 1 },
+    Vector3 { x: -1, y: 0, z: -1 },
+];
+
+// axial coordinates, `x` is q and `z` is r
+const HEX: [Vector3<i32>; 6] = [
+    Vector3 { x: 1, y: 0, z: 0 },
+    Vector3 { x: 1, y: 0, z: -1 },
+    Vector3 { x: 0, y: 0, z: -1 },
+    Vector3 { x: -1, y: 0, z: 0 },
+    Vector3 { x: -1, y: 0, z: 1 },
+    Vector3 { x: 0, y: 0, z: 1 },
+];
+
+const VERTICAL: [Vector3<i32>; 2] = [
+    Vector3 { x: 0, y: 1, z: 0 },
+    Vector3 { x: 0, y: -1, z: 0 },
+];
+
+// matches the pointy-topped hex from `renderer::model::make_hex`
+const HEX_OUTER: f32 = 1.0;
+const HEX_INNER: f32 = HEX_OUTER * 0.866_025_404;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MapTopology {
+    Square4,
+    Square8,
+    Hex,
+}
+
+impl Default for MapTopology {
+    fn default() -> Self {
+        MapTopology::Square4
+    }
+}
+
+impl MapTopology {
+    pub fn directions(&self) -> &'static [Vector3<i32>] {
+        match self {
+            MapTopology::Square4 => &SQUARE_4,
+            MapTopology::Square8 => &SQUARE_8,
+            MapTopology::Hex => &HEX,
+        }
+    }
+
+    pub fn vertical_directions(&self) -> &'static [Vector3<i32>] {
+        &VERTICAL
+    }
+
+    // number of steps between two cells
+    pub fn distance(&self, a: &Vector3<i32>, b: &Vector3<i32>) -> i32 {
+        let d = *a - *b;
+        let (dx, dy, dz) = (d.x.abs(), d.y.abs(), d.z.abs());
+
+        let planar = match self {
+            MapTopology::Square4 => dx + dz,
+            MapTopology::Square8 => dx.max(dz),
+            MapTopology::Hex => (dx + dz + (d.x + d.z).abs()) / 2,
+        };
+
+        planar + dy
+    }
+
+    // every cell from `a` to `b`, including both ends
+    pub fn line(&self, a: &Vector3<i32>, b: &Vector3<i32>) -> Vec<Vector3<i32>> {
+        match self {
+            MapTopology::Square4 | MapTopology::Square8 => bresenham(a, b),
+            MapTopology::Hex => hex_line(a, b),
+        }
+    }
+
+    pub fn to_world(&self, cell: &Vector3<i32>) -> Vector3<f32> {
+        match self {
+            MapTopology::Square4 | MapTopology::Square8 => {
+                Vector3::new(cell.x as f32, cell.y as f32, cell.z as f32)
+            },
+            MapTopology::Hex => {
+                let q = cell.x as f32;
+                let r = cell.z as f32;
+
+                Vector3::new(
+                    (q + r * 0.5) * (HEX_INNER * 2.0),
+                    cell.y as f32,
+                    r * (HEX_OUTER * 1.5),
+                )
+            },
+        }
+    }
+}
+
+fn bresenham(a: &Vector3<i32>, b: &Vector3<i32>) -> Vec<Vector3<i32>> {
+    let delta = [b.x - a.x, b.y - a.y, b.z - a.z];
+    let steps = [delta[0].signum(), delta[1].signum(), delta[2].signum()];
+    let lengths = [delta[0].abs(), delta[1].abs(), delta[2].abs()];
+
+    let major = (0..3).max_by_key(|axis| lengths[*axis]).unwrap();
+    let length = lengths[major];
+
+    let mut current = [a.x, a.y, a.z];
+    let mut errors = [0; 3];
+    let mut cells = vec![*a];
+
+    for _ in 0..length {
+        current[major] += steps[major];
+
+        for axis in 0..3 {
+            if axis == major {
+                continue;
+            }
+
+            errors[axis] += lengths[axis] * 2;
+            if errors[axis] > length {
+                current[axis] += steps[axis];
+                errors[axis] -= length * 2;
+            }
+        }
+
+        cells.push(Vector3::new(current[0], current[1], current[2]));
+    }
+
+    cells
+}
+
+fn hex_line(a: &Vector3<i32>, b: &Vector3<i32>) -> Vec<Vector3<i32>> {
+    let planar = MapTopology::Hex.distance(&Vector3::new(a.x, 0, a.z), &Vector3::new(b.x, 0, b.z));
+    let steps = planar.max((b.y - a.y).abs());
+
+    if steps == 0 {
+        return vec![*a];
+    }
+
+    let lerp = |from: i32, to: i32, t: f64| from as f64 + (to - from) as f64 * t;
+
+    (0..steps + 1)
+        .map(|i| {
+            // nudged so that lines along hex edges don't round both ways
+            let t = i as f64 / steps as f64;
+            let q = lerp(a.x, b.x, t) + 1e-6;
+            let r = lerp(a.z, b.z, t) + 1e-6;
+            let s = -q - r;
+
+            let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+            let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+
+            if dq > dr && dq > ds {
+                rq = -rr - rs;
+            } else if dr > ds {
+                rr = -rq - rs;
+            }
+
+            Vector3::new(rq as i32, lerp(a.y, b.y, t).round() as i32, rr as i32)
+        })
+        .collect()
+}
@@ -0,0 +1,332 @@
+use std::collections::HashSet;
+use cgmath::Vector3;
+use specs::{ Entities, Fetch, ReadStorage, System, WriteStorage };
+use crate::{ CollisionLayer, CollisionLayers, Data, EventBus, EventReader, Map, MapEvent, MapTopology, RLock };
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SightBlockers {
+    pub layers: HashSet<CollisionLayer>,
+    pub terrain: bool,
+}
+
+impl Map {
+    pub fn blocks_sight<'a>(&self, location: &Vector3<i32>, blockers: &SightBlockers, collision_layers: &ReadStorage<'a, CollisionLayers>) -> bool {
+        if blockers.terrain && self.terrain().is_opaque(location) {
+            return true;
+        }
+
+        match self.entities(location) {
+            Some(mut entities) => entities.any(|entity| {
+                collision_layers.get(*entity)
+                    .map(|layers| layers.iter().any(|layer| blockers.layers.contains(layer)))
+                    .unwrap_or(false)
+            }),
+            None => false,
+        }
+    }
+
+    // the cells at either end of the line never block
+    pub fn line_of_sight(&self, from: &Vector3<i32>, to: &Vector3<i32>, is_opaque: impl Fn(&Vector3<i32>) -> bool) -> bool {
+        let line = self.topology().line(from, to);
+        let between = line.len().saturating_sub(2);
+
+        line.iter()
+            .skip(1)
+            .take(between)
+            .all(|cell| !is_opaque(cell))
+    }
+
+    // only looks along the `y` level of `origin`
+    pub fn field_of_view(&self, origin: &Vector3<i32>, radius: i32, is_opaque: impl Fn(&Vector3<i32>) -> bool) -> HashSet<Vector3<i32>> {
+        match self.topology() {
+            MapTopology::Square4 | MapTopology::Square8 => shadowcast(self, origin, radius, &is_opaque),
+            MapTopology::Hex => {
+                let mut visible = HashSet::new();
+
+                for q in (origin.x - radius)..(origin.x + radius + 1) {
+                    for r in (origin.z - radius)..(origin.z + radius + 1) {
+                        let cell = Vector3::new(q, origin.y, r);
+
+                        if  self.in_bounds(&cell) &&
+                            self.distance(origin, &cell) <= radius &&
+                            self.line_of_sight(origin, &cell, &is_opaque) {
+                                visible.insert(cell);
+                        }
+                    }
+                }
+
+                visible
+            },
+        }
+    }
+}
+
+fn floor_div(a: i32, b: i32) -> i32 {
+    if a >= 0 { a / b } else { -((-a + b - 1) / b) }
+}
+
+fn ceil_div(a: i32, b: i32) -> i32 {
+    -floor_div(-a, b)
+}
+
+// a fraction, so that the shadowcasting is exact
+#[derive(Copy, Clone, Debug)]
+struct Slope {
+    num: i32,
+    den: i32,
+}
+
+impl Slope {
+    fn of(depth: i32, col: i32) -> Self {
+        Slope { num: 2 * col - 1, den: 2 * depth }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Row {
+    depth: i32,
+    start: Slope,
+    end: Slope,
+}
+
+impl Row {
+    fn min_col(&self) -> i32 {
+        floor_div(2 * self.depth * self.start.num + self.start.den, 2 * self.start.den)
+    }
+
+    fn max_col(&self) -> i32 {
+        ceil_div(2 * self.depth * self.end.num - self.end.den, 2 * self.end.den)
+    }
+
+    fn is_symmetric(&self, col: i32) -> bool {
+        col * self.start.den >= self.depth * self.start.num &&
+        col * self.end.den <= self.depth * self.end.num
+    }
+
+    fn next(&self) -> Row {
+        Row { depth: self.depth + 1, .. *self }
+    }
+}
+
+// https://www.albertford.com/shadowcasting/
+fn shadowcast(map: &Map, origin: &Vector3<i32>, radius: i32, is_opaque: &impl Fn(&Vector3<i32>) -> bool) -> HashSet<Vector3<i32>> {
+    let mut visible = HashSet::new();
+    visible.insert(*origin);
+
+    for quadrant in 0..4 {
+        let transform = |depth: i32, col: i32| match quadrant {
+            0 => Vector3::new(origin.x + col, origin.y, origin.z - depth),
+            1 => Vector3::new(origin.x + col, origin.y, origin.z + depth),
+            2 => Vector3::new(origin.x + depth, origin.y, origin.z + col),
+            _ => Vector3::new(origin.x - depth, origin.y, origin.z + col),
+        };
+
+        // the edge of the map acts like a wall
+        let is_wall = |cell: &Vector3<i32>| !map.in_bounds(cell) || is_opaque(cell);
+
+        let mut rows = vec![Row {
+            depth: 1,
+            start: Slope { num: -1, den: 1 },
+            end: Slope { num: 1, den: 1 },
+        }];
+
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius {
+                continue;
+            }
+
+            let mut previous_wall = None;
+
+            for col in row.min_col()..(row.max_col() + 1) {
+                let cell = transform(row.depth, col);
+                let wall = is_wall(&cell);
+
+                if  (wall || row.is_symmetric(col)) &&
+                    map.in_bounds(&cell) &&
+                    map.distance(origin, &cell) <= radius {
+                        visible.insert(cell);
+                }
+
+                if previous_wall == Some(true) && !wall {
+                    row.start = Slope::of(row.depth, col);
+                }
+
+                if previous_wall == Some(false) && wall {
+                    let mut next = row.next();
+                    next.end = Slope::of(row.depth, col);
+                    rows.push(next);
+                }
+
+                previous_wall = Some(wall);
+            }
+
+            if previous_wall == Some(false) {
+                rows.push(row.next());
+            }
+        }
+    }
+
+    visible
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct Viewshed {
+    radius: i32,
+    blockers: SightBlockers,
+    visible: HashSet<Vector3<i32>>,
+    origin: Option<Vector3<i32>>,
+    dirty: bool,
+}
+
+impl Viewshed {
+    pub fn new(radius: i32, blockers: SightBlockers) -> Self {
+        Self {
+            radius,
+            blockers,
+            visible: HashSet::new(),
+            origin: None,
+            dirty: true,
+        }
+    }
+
+    pub fn radius(&self) -> i32 {
+        self.radius
+    }
+
+    pub fn set_radius(&mut self, radius: i32) {
+        self.radius = radius;
+        self.dirty = true;
+    }
+
+    pub fn blockers(&self) -> &SightBlockers {
+        &self.blockers
+    }
+
+    pub fn set_blockers(&mut self, blockers: SightBlockers) {
+        self.blockers = blockers;
+        self.dirty = true;
+    }
+
+    pub fn visible(&self) -> &HashSet<Vector3<i32>> {
+        &self.visible
+    }
+
+    pub fn can_see(&self, location: &Vector3<i32>) -> bool {
+        self.visible.contains(location)
+    }
+
+    // recalculate even if nothing on the map has changed, e.g. when a door opens
+    pub fn mark_dirty(&mut self) {
+        self.dirty = true;
+    }
+}
+
+pub struct VisibilitySystem {
+    // subscribed the first time the system runs
+    map_events: Option<EventReader<MapEvent>>,
+}
+
+impl VisibilitySystem {
+    pub fn new() -> Self {
+        VisibilitySystem {
+            map_events: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for VisibilitySystem {
+    type SystemData =  (Entities<'a>,
+                        WriteStorage<'a, Viewshed>,
+                        ReadStorage<'a, CollisionLayers>,
+                        ReadStorage<'a, Data>,
+                        Fetch<'a, RLock<Map>>,
+                        Fetch<'a, EventBus>);
+
+    fn run(&mut self, (entities, mut viewsheds, collision_layers, datas, map, events): Self::SystemData) {
+        use specs::Join;
+
+        let map = map.read().unwrap();
+
+        let map_events = match &mut self.map_events {
+            Some(map_events) => map_events.read(),
+            None => {
+                self.map_events = Some(events.subscribe::<MapEvent>());
+                vec![]
+            },
+        };
+
+        // something that blocks sight may have moved in or out of view
+        for event in map_events {
+            for viewshed in (&mut viewsheds).join() {
+                let changed = match event {
+                    MapEvent::EntityMoved { from, to, .. } => viewshed.can_see(&from) || viewshed.can_see(&to),
+                    MapEvent::EntityPlaced { location, .. } | MapEvent::EntityRemoved { location, .. } => viewshed.can_see(&location),
+                    MapEvent::MapReset { .. } => true,
+                    MapEvent::MoveRejected { .. } | MapEvent::PlacementFailed { .. } => false,
+                };
+
+                if changed {
+                    viewshed.dirty = true;
+                }
+            }
+        }
+
+        for (entity, viewshed) in (&*entities, &mut viewsheds).join() {
+            let origin = match map.location(&entity) {
+                Some(origin) => *origin,
+                None => continue,
+            };
+
+            if !viewshed.dirty && viewshed.origin == Some(origin) {
+                continue;
+            }
+
+            viewshed.visible = {
+                let blockers = &viewshed.blockers;
+                map.field_of_view(&origin, viewshed.radius, |cell| map.blocks_sight(cell, blockers, &collision_layers))
+            };
+            viewshed.origin = Some(origin);
+            viewshed.dirty = false;
+
+            if let Some(data) = datas.get(entity) {
+                data.insert(viewshed.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sees_everything_in_an_open_room() {
+        let map = Map::new(10, 10, 0, MapTopology::Square8);
+        let visible = map.field_of_view(&Vector3::new(5, 0, 5), 2, |_| false);
+
+        assert_eq!(visible.len(), 25);
+        assert!(visible.iter().all(|cell| map.distance(&Vector3::new(5, 0, 5), cell) <= 2));
+    }
+
+    #[test]
+    fn walls_are_seen_but_hide_what_is_behind_them() {
+        let map = Map::new(10, 10, 0, MapTopology::Square8);
+        let wall = Vector3::new(5, 0, 3);
+        let visible = map.field_of_view(&Vector3::new(5, 0, 5), 5, |cell| *cell == wall);
+
+        assert!(visible.contains(&wall));
+        assert!(!visible.contains(&Vector3::new(5, 0, 2)));
+        assert!(!visible.contains(&Vector3::new(5, 0, 1)));
+        assert!(visible.contains(&Vector3::new(5, 0, 7)));
+    }
+
+    #[test]
+    fn stops_at_the_edge_of_the_map() {
+        let map = Map::new(10, 10, 0, MapTopology::Square4);
+        let visible = map.field_of_view(&Vector3::new(0, 0, 0), 3, |_| false);
+
+        assert!(visible.iter().all(|cell| map.in_bounds(cell)));
+        assert!(visible.contains(&Vector3::new(3, 0, 0)));
+        assert!(!visible.contains(&Vector3::new(2, 0, 2)));
+    }
+}
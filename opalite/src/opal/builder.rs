@@ -1,29 +1,48 @@
 use std::collections::HashMap;
 use cgmath::{ Deg, Vector3 };
 use conrod::{ UiBuilder };
+use failure::Error;
 use gluon;
 use rusttype;
 use specs::{ DispatcherBuilder, World };
 use winit::{ EventsLoop, WindowBuilder, Window };
-use super::{ DefaultSystems, Gluon, GluonUi, Opal, OpalUi, WindowClosed };
+use super::{ DefaultSystems, Gluon, GluonUi, HeadlessOpal, Opal, OpalUi, UiCapture, WindowClosed };
 use crate::{
     AiComponent,
+    AiScript,
     Camera,
     CollisionLayers,
     Config,
     ConfigBuilder,
     Data,
+    EventBus,
     InitialPosition,
     InputEventHandler,
     InputEventType,
+    InputMap,
+    Initiative,
+    Interpolation,
+    LevelDesc,
+    MapMessage,
+    MessageSender,
     ModelData,
     ModelKey,
+    Placement,
     Position,
     Renderer,
+    ReplayHandle,
     RLock,
+    PrefabLibrary,
+    PrefabSpawner,
+    SaveRegistry,
+    Terrain,
+    Time,
+    TurnScheduler,
     Resources,
+    Viewshed,
+    load_level,
 };
-use crate::gluon_api::{ self, DataReference, GluonUiComponent, RequireMap };
+use crate::gluon_api::{ self, DataReference, GluonUiComponent, RequireMap, RequirePrefabs, RequireSelection };
 use crate::renderer::{ Light, MaterialDesc };
 
 #[allow(non_snake_case)]
@@ -33,6 +52,8 @@ mod BuilderState {
     pub struct DispatcherEnd;
     pub struct DispatcherThreadLocal;
     pub struct World;
+    pub struct Headless;
+    pub struct HeadlessWorld;
 }
 
 pub struct OpalBuilder;
@@ -41,7 +62,9 @@ pub struct PartialOpalBuilder<'a, 'b, S> {
     config: Config,
     default_systems: DefaultSystems,
     dispatcher: Option<DispatcherBuilder<'a, 'b>>,
-    events_loop: EventsLoop,
+    render_dispatcher: Option<DispatcherBuilder<'a, 'b>>,
+    // only created once a window is needed
+    events_loop: Option<EventsLoop>,
     gluon: gluon::RootedThread,
     resources: RLock<Resources>,
     window: Option<Window>,
@@ -67,18 +90,19 @@ impl OpalBuilder {
             }
         };
 
-        let default_systems = DefaultSystems::new(&config);
-
         let resources = Resources::from_config(&config).unwrap();
         let resources = RLock::new(resources);
 
+        let default_systems = DefaultSystems::new(&config, &resources);
+
         let gluon = gluon::new_vm();
 
         PartialOpalBuilder {
             config,
             default_systems,
             dispatcher: None,
-            events_loop: EventsLoop::new(),
+            render_dispatcher: None,
+            events_loop: None,
             gluon,
             resources,
             window: None,
@@ -93,21 +117,123 @@ impl<'a, 'b, S> PartialOpalBuilder<'a, 'b, S> {
         self.dispatcher.as_mut()
     }
 
+    // systems that run once per rendered frame instead of once per tick
+    pub fn render_dispatcher_builder(&mut self) -> Option<&mut DispatcherBuilder<'a, 'b>> {
+        self.render_dispatcher.as_mut()
+    }
+
     pub fn gluon(&mut self) -> &mut gluon::RootedThread {
         &mut self.gluon
     }
+
+    fn create_world(&mut self) -> World {
+        let mut world = World::new();
+
+        world.register::<AiComponent>();
+        world.register::<AiScript>();
+        world.register::<CollisionLayers>();
+        world.register::<Data>();
+        world.register::<DataReference>();
+        world.register::<GluonUiComponent>();
+        world.register::<Initiative>();
+        world.register::<Interpolation>();
+        world.register::<Light>();
+        world.register::<MaterialDesc>();
+        world.register::<ModelData>();
+        world.register::<ModelKey>();
+        world.register::<InitialPosition>();
+        world.register::<Placement>();
+        world.register::<Position>();
+        world.register::<RequireMap>();
+        world.register::<RequirePrefabs>();
+        world.register::<RequireSelection>();
+        world.register::<Viewshed>();
+
+        world.add_resource(self.default_systems.map_reader.take().unwrap());
+        world.add_resource(self.default_systems.selection_reader.take().unwrap());
+        world.add_resource(self.default_systems.map_system_sender.take().unwrap());
+        world.add_resource(EventBus::default());
+        world.add_resource(ReplayHandle::new());
+        world.add_resource(SaveRegistry::with_defaults());
+        world.add_resource(PrefabSpawner::new());
+        world.add_resource(TurnScheduler::new(self.config.turn_based));
+        world.add_resource(self.config.clone());
+        world.add_resource(WindowClosed(false));
+        world.add_resource(Camera {
+            position: Vector3::new(1.0, 2.5, 5.0),
+            direction: Vector3::new(0.0, -0.5, -1.0),
+            fovy: Deg(45.0),
+            near: 0.1,
+            far: 100.0,
+        });
+
+        world
+    }
+}
+
+// resources that can fail to be created, added by `build` so that it can return the error
+fn add_loaded_resources(world: &mut World, config: &Config, resources: &RLock<Resources>) -> Result<(), Error> {
+    world.add_resource(Time::new(config.tick_rate)?);
+    world.add_resource(PrefabLibrary::load(&config.prefabs, resources)?);
+
+    // the map is replaced with one that has the terrain before the first tick
+    if let Some(terrain) = &config.terrain {
+        let terrain = Terrain::load(terrain, resources)?;
+        world.write_resource::<MessageSender<MapMessage>>().send(MapMessage::Reset {
+            dimensions: config.map_dimensions.into(),
+            topology: config.map_topology,
+            terrain,
+        });
+    }
+
+    Ok(())
+}
+
+fn add_input_event_handler(world: &mut World, config: &Config, resources: &RLock<Resources>, default_systems: &mut DefaultSystems) -> Result<InputEventHandler, Error> {
+    let mut input_event_handler = InputEventHandler::new();
+    let picker_system_sender = default_systems.picker_system_sender.take().unwrap();
+    input_event_handler.register(InputEventType::MouseClickedWithCoordinates, picker_system_sender.clone());
+    input_event_handler.register(InputEventType::MouseCoordinates, picker_system_sender.clone());
+    input_event_handler.register(InputEventType::Resized, picker_system_sender);
+    input_event_handler.set_replay(Some(world.read_resource::<ReplayHandle>().clone()));
+    input_event_handler.set_event_bus(Some(world.read_resource::<EventBus>().clone()));
+
+    if let Some(input_map) = &config.input_map {
+        input_event_handler.set_input_map(InputMap::load(input_map, resources)?);
+    }
+    world.add_resource(input_event_handler.action_state());
+
+    Ok(input_event_handler)
+}
+
+fn add_gluon(world: &mut World, resources: RLock<Resources>, gluon: gluon::RootedThread) {
+    world.add_resource(OpalUi(None));
+    world.add_resource(GluonUi(HashMap::new()));
+    world.add_resource(UiCapture::default());
+    world.add_resource(resources);
+
+    gluon_api::register_opalite_api(&gluon);
+
+    world.add_resource(Gluon {
+        thread: gluon,
+        compiler: gluon::Compiler::new().run_io(true),
+    });
 }
 
 impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::New> {
     pub fn add_dispatcher_start(mut self) -> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherStart> {
         let dispatcher = DispatcherBuilder::new()
             .add(self.default_systems.data_ref_system.take().unwrap(), "DataReferenceSystem", &[])
-            .add(self.default_systems.require_map_system.take().unwrap(), "RequireMapSystem", &[]);
+            .add(self.default_systems.require_map_system.take().unwrap(), "RequireMapSystem", &[])
+            .add(self.default_systems.require_prefabs_system.take().unwrap(), "RequirePrefabsSystem", &[])
+            .add(self.default_systems.require_selection_system.take().unwrap(), "RequireSelectionSystem", &[])
+            .add(self.default_systems.turn_system.take().unwrap(), "TurnSystem", &[]);
 
         PartialOpalBuilder {
             config: self.config,
             default_systems: self.default_systems,
             dispatcher: Some(dispatcher),
+            render_dispatcher: self.render_dispatcher,
             events_loop: self.events_loop,
             gluon: self.gluon,
             resources: self.resources,
@@ -124,13 +250,15 @@ impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherStart> {
             .unwrap()
             .add_barrier()
             .add(self.default_systems.picker_system.take().unwrap(), "PickerSystem", &[])
-            .add(self.default_systems.ai_system.take().unwrap(), "AiSystem", &[])
-            .add(self.default_systems.map_system.take().unwrap(), "MapSystem", &["AiSystem"]);
+            .add(self.default_systems.ai_system.take().unwrap(), "AiSystem", &["TurnSystem"])
+            .add(self.default_systems.map_system.take().unwrap(), "MapSystem", &["AiSystem"])
+            .add(self.default_systems.visibility_system.take().unwrap(), "VisibilitySystem", &["MapSystem"]);
 
         PartialOpalBuilder {
             config: self.config,
             default_systems: self.default_systems,
             dispatcher: Some(dispatcher),
+            render_dispatcher: self.render_dispatcher,
             events_loop: self.events_loop,
             gluon: self.gluon,
             resources: self.resources,
@@ -143,25 +271,26 @@ impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherStart> {
 
 impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherEnd> {
     pub fn add_dispatcher_thread_local(mut self) -> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherThreadLocal> {
+        let events_loop = EventsLoop::new();
         let window = WindowBuilder::new()
             .with_dimensions(self.config.window_dimensions.0, self.config.window_dimensions.1)
             .with_title(self.config.title.clone())
-            .build(&self.events_loop)
+            .build(&events_loop)
             .unwrap();
 
         let renderer = Renderer::new(self.config.clone(), self.resources.clone(), &window).unwrap();
 
-        let dispatcher = self.dispatcher.take()
-            .unwrap()
-            .add_barrier()
+        let render_dispatcher = DispatcherBuilder::new()
             .add(self.default_systems.gluon_ui_system.take().unwrap(), "GluonUiSystem", &[])
+            .add(self.default_systems.interpolation_system.take().unwrap(), "InterpolationSystem", &[])
             .add_thread_local(renderer);
 
         PartialOpalBuilder {
             config: self.config,
             default_systems: self.default_systems,
-            dispatcher: Some(dispatcher),
-            events_loop: self.events_loop,
+            dispatcher: self.dispatcher,
+            render_dispatcher: Some(render_dispatcher),
+            events_loop: Some(events_loop),
             gluon: self.gluon,
             resources: self.resources,
             window: Some(window),
@@ -169,45 +298,36 @@ impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherEnd> {
             state: BuilderState::DispatcherThreadLocal,
         }
     }
+
+    // skips the window, renderer and UI, see `HeadlessOpal`
+    pub fn add_headless(mut self) -> PartialOpalBuilder<'a, 'b, BuilderState::Headless> {
+        let render_dispatcher = DispatcherBuilder::new()
+            .add(self.default_systems.interpolation_system.take().unwrap(), "InterpolationSystem", &[]);
+
+        PartialOpalBuilder {
+            config: self.config,
+            default_systems: self.default_systems,
+            dispatcher: self.dispatcher,
+            render_dispatcher: Some(render_dispatcher),
+            events_loop: None,
+            gluon: self.gluon,
+            resources: self.resources,
+            window: None,
+            world: self.world,
+            state: BuilderState::Headless,
+        }
+    }
 }
 
 impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherThreadLocal> {
     pub fn add_world(mut self) -> PartialOpalBuilder<'a, 'b, BuilderState::World> {
-        let world = {
-            let mut world = World::new();
-
-            world.register::<AiComponent>();
-            world.register::<CollisionLayers>();
-            world.register::<Data>();
-            world.register::<DataReference>();
-            world.register::<GluonUiComponent>();
-            world.register::<Light>();
-            world.register::<MaterialDesc>();
-            world.register::<ModelData>();
-            world.register::<ModelKey>();
-            world.register::<InitialPosition>();
-            world.register::<Position>();
-            world.register::<RequireMap>();
-
-            world.add_resource(self.default_systems.map_reader.take().unwrap());
-            world.add_resource(self.default_systems.map_system_sender.take().unwrap());
-            world.add_resource(self.config.clone());
-            world.add_resource(WindowClosed(false));
-            world.add_resource(Camera {
-                position: Vector3::new(1.0, 2.5, 5.0),
-                direction: Vector3::new(0.0, -0.5, -1.0),
-                fovy: Deg(45.0),
-                near: 0.1,
-                far: 100.0,
-            });
-
-            world
-        };
+        let world = self.create_world();
 
         PartialOpalBuilder {
             config: self.config,
             default_systems: self.default_systems,
             dispatcher: self.dispatcher,
+            render_dispatcher: self.render_dispatcher,
             events_loop: self.events_loop,
             gluon: self.gluon,
             resources: self.resources,
@@ -219,14 +339,17 @@ impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::DispatcherThreadLocal> {
 }
 
 impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::World> {
-    pub fn build(self) -> Opal<'a, 'b> {
-        let PartialOpalBuilder { config, mut default_systems, dispatcher, events_loop, gluon, resources, window, world, .. } = self;
+    // fails if the level in `Config::level` can't be loaded
+    pub fn build(self) -> Result<Opal<'a, 'b>, Error> {
+        let PartialOpalBuilder { config, mut default_systems, dispatcher, render_dispatcher, events_loop, gluon, resources, window, world, .. } = self;
         let dispatcher = dispatcher.unwrap().build();
+        let render_dispatcher = render_dispatcher.unwrap().build();
+        let events_loop = events_loop.unwrap();
         let window = window.unwrap();
         let mut world = world.unwrap();
 
-        let mut input_event_handler = InputEventHandler::new();
-        input_event_handler.register(InputEventType::MouseClickedWithCoordinates, default_systems.picker_system_sender.take().unwrap());
+        add_loaded_resources(&mut world, &config, &resources)?;
+        let input_event_handler = add_input_event_handler(&mut world, &config, &resources, &mut default_systems)?;
 
         let (width, height) = window.get_inner_size().unwrap();
         let mut ui = UiBuilder::new([width as f64, height as f64])
@@ -239,17 +362,54 @@ impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::World> {
             ui.fonts.insert(font);
         }
 
-        world.add_resource(OpalUi(None));
-        world.add_resource(GluonUi(HashMap::new()));
-        world.add_resource(resources);
+        add_gluon(&mut world, resources, gluon);
+
+        if let Some(level) = &config.level {
+            let level = LevelDesc::load(level, &world.read_resource::<RLock<Resources>>())?;
+            load_level(&mut world, level)?;
+        }
+
+        Ok(Opal { config, dispatcher, render_dispatcher, events_loop, input_event_handler, ui, window, world })
+    }
+}
 
-        gluon_api::register_opalite_api(&gluon);
+impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::Headless> {
+    pub fn add_world(mut self) -> PartialOpalBuilder<'a, 'b, BuilderState::HeadlessWorld> {
+        let world = self.create_world();
 
-        world.add_resource(Gluon {
-            thread: gluon,
-            compiler: gluon::Compiler::new().run_io(true),
-        });
+        PartialOpalBuilder {
+            config: self.config,
+            default_systems: self.default_systems,
+            dispatcher: self.dispatcher,
+            render_dispatcher: self.render_dispatcher,
+            events_loop: self.events_loop,
+            gluon: self.gluon,
+            resources: self.resources,
+            window: self.window,
+            world: Some(world),
+            state: BuilderState::HeadlessWorld,
+        }
+    }
+}
+
+impl<'a, 'b> PartialOpalBuilder<'a, 'b, BuilderState::HeadlessWorld> {
+    // fails if the level in `Config::level` can't be loaded
+    pub fn build(self) -> Result<HeadlessOpal<'a, 'b>, Error> {
+        let PartialOpalBuilder { config, mut default_systems, dispatcher, render_dispatcher, gluon, resources, world, .. } = self;
+        let dispatcher = dispatcher.unwrap().build();
+        let render_dispatcher = render_dispatcher.unwrap().build();
+        let mut world = world.unwrap();
+
+        add_loaded_resources(&mut world, &config, &resources)?;
+        let input_event_handler = add_input_event_handler(&mut world, &config, &resources, &mut default_systems)?;
+
+        add_gluon(&mut world, resources, gluon);
+
+        if let Some(level) = &config.level {
+            let level = LevelDesc::load(level, &world.read_resource::<RLock<Resources>>())?;
+            load_level(&mut world, level)?;
+        }
 
-        Opal { config, dispatcher, events_loop, input_event_handler, ui, window, world }
+        Ok(HeadlessOpal { config, dispatcher, render_dispatcher, input_event_handler, world })
     }
 }
@@ -2,48 +2,72 @@ use crate::{
     AiSystem,
     Config,
     InputEvent,
+    InterpolationSystem,
     MapMessage,
     MessageSender,
     Map,
     MapSystem,
     PickerSystem,
+    Resources,
     RLock,
+    Selection,
     Shard,
+    TurnSystem,
+    VisibilitySystem,
 };
-use crate::gluon_api::{ GluonUiSystem, DataReferenceSystem, RequireMapSystem };
+use crate::gluon_api::{ GluonUiSystem, DataReferenceSystem, RequireMapSystem, RequirePrefabsSystem, RequireSelectionSystem };
 
 pub struct DefaultSystems {
     pub(super) ai_system: Option<AiSystem>,
     pub(super) data_ref_system: Option<DataReferenceSystem>,
     pub(super) gluon_ui_system: Option<GluonUiSystem>,
+    pub(super) interpolation_system: Option<InterpolationSystem>,
     pub(super) map_system: Option<MapSystem>,
     pub(super) map_system_sender: Option<MessageSender<MapMessage>>,
     pub(super) map_reader: Option<RLock<Map>>,
     pub(super) picker_system: Option<PickerSystem>,
     pub(super) picker_system_sender: Option<MessageSender<InputEvent>>,
+    pub(super) selection_reader: Option<RLock<Selection>>,
     pub(super) require_map_system: Option<RequireMapSystem>,
+    pub(super) require_prefabs_system: Option<RequirePrefabsSystem>,
+    pub(super) require_selection_system: Option<RequireSelectionSystem>,
+    pub(super) turn_system: Option<TurnSystem>,
+    pub(super) visibility_system: Option<VisibilitySystem>,
 }
 
 impl DefaultSystems {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(config: &Config, resources: &RLock<Resources>) -> Self {
         let map_dimensions = config.map_dimensions.into();
-        let map_system = MapSystem::new(map_dimensions);
+        // `Config::terrain` is loaded by the builder, so that a bad file is an error from `build`
+        let mut map_system = MapSystem::new(map_dimensions, config.map_topology);
+        map_system.set_placement_policy(config.placement_policy);
+
+        let ai_system = AiSystem::new();
+
         let map_system_sender = map_system.sender();
         let map_reader = map_system.map();
         let (width, height) = config.window_dimensions;
-        let picker_system = PickerSystem::new(width, height);
+        let mut picker_system = PickerSystem::new(width, height, resources.clone());
+        picker_system.set_pick_triangles(config.pick_triangles);
         let picker_system_sender = picker_system.sender();
+        let selection_reader = picker_system.selection();
 
         Self {
-            ai_system: Some(AiSystem::new()),
+            ai_system: Some(ai_system),
             data_ref_system: Some(DataReferenceSystem::new()),
             gluon_ui_system: Some(GluonUiSystem::new()),
+            interpolation_system: Some(InterpolationSystem::new()),
             map_system: Some(map_system),
             map_system_sender: Some(map_system_sender),
             map_reader: Some(map_reader),
             picker_system: Some(picker_system),
             picker_system_sender: Some(picker_system_sender),
+            selection_reader: Some(selection_reader),
             require_map_system: Some(RequireMapSystem::new()),
+            require_prefabs_system: Some(RequirePrefabsSystem::new()),
+            require_selection_system: Some(RequireSelectionSystem::new()),
+            turn_system: Some(TurnSystem::new()),
+            visibility_system: Some(VisibilitySystem::new()),
         }
     }
 }
@@ -0,0 +1,132 @@
+use std::path::PathBuf;
+use cgmath::Vector3;
+use failure::Error;
+use specs::{ Dispatcher, Entity, FetchMut, World };
+use crate::{
+    load_level,
+    spawn_prefab,
+    Config,
+    EventBus,
+    InputEvent,
+    InputEventHandler,
+    LevelDesc,
+    PrefabSpawner,
+    ReplayFile,
+    ReplayHandle,
+    Resources,
+    RLock,
+    SaveFile,
+    SaveRegistry,
+    Time,
+};
+
+// an `Opal` without a window, renderer or UI, for tests and servers
+pub struct HeadlessOpal<'a, 'b> {
+    pub(super) config: Config,
+    pub(super) dispatcher: Dispatcher<'a, 'b>,
+    pub(super) render_dispatcher: Dispatcher<'a, 'b>,
+    pub(super) input_event_handler: InputEventHandler,
+    pub(super) world: World,
+}
+
+impl<'a, 'b> HeadlessOpal<'a, 'b> {
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn input_event_handler(&self) -> &InputEventHandler {
+        &self.input_event_handler
+    }
+
+    pub fn input_event_handler_mut(&mut self) -> &mut InputEventHandler {
+        &mut self.input_event_handler
+    }
+
+    // handled by the next `step`, like input from a window would be
+    pub fn send_input(&mut self, event: InputEvent) {
+        let next_tick = self.world.read_resource::<Time>().tick() + 1;
+        self.replay().begin_tick(next_tick);
+
+        if !self.replay().is_playing() {
+            self.input_event_handler.send(event);
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn spawn_prefab(&mut self, name: &str, position: Vector3<i32>) -> Result<Entity, Error> {
+        spawn_prefab(&mut self.world, name, position)
+    }
+
+    // replaces every entity that's on the map, returns the entities the level created
+    pub fn load_level(&mut self, path: &PathBuf) -> Result<Vec<Entity>, Error> {
+        let level = {
+            let resources = self.world.read_resource::<RLock<Resources>>();
+            LevelDesc::load(path, &resources)?
+        };
+        load_level(&mut self.world, level)
+    }
+
+    pub fn save_registry_mut(&self) -> FetchMut<SaveRegistry> {
+        self.world.write_resource::<SaveRegistry>()
+    }
+
+    pub fn save_game(&self, path: &PathBuf) -> Result<(), Error> {
+        let registry = self.world.read_resource::<SaveRegistry>().clone();
+        registry.save(&self.world)?.save(path)
+    }
+
+    pub fn load_game(&mut self, path: &PathBuf) -> Result<Vec<Entity>, Error> {
+        let file = SaveFile::load(path)?;
+        let registry = self.world.read_resource::<SaveRegistry>().clone();
+        registry.load(&mut self.world, file)
+    }
+
+    pub fn replay(&self) -> ReplayHandle {
+        self.world.read_resource::<ReplayHandle>().clone()
+    }
+
+    // records input from the next tick on, call `save_replay` to write it out
+    pub fn record_replay(&mut self) {
+        let next_tick = self.world.read_resource::<Time>().tick() + 1;
+        self.replay().record(next_tick);
+    }
+
+    pub fn save_replay(&self, path: &PathBuf) -> Result<(), Error> {
+        self.replay().save(path)
+    }
+
+    // input from `send_input` is ignored until the replay has finished
+    pub fn play_replay(&mut self, path: &PathBuf) -> Result<(), Error> {
+        let file = ReplayFile::load(path)?;
+        let next_tick = self.world.read_resource::<Time>().tick() + 1;
+        self.replay().play(file, next_tick);
+        Ok(())
+    }
+
+    // each tick counts as one frame of `Time::fixed_delta`, however long it takes to run
+    pub fn step(&mut self, ticks: u64) {
+        let HeadlessOpal { dispatcher, render_dispatcher, input_event_handler, world, .. } = self;
+        let replay = world.read_resource::<ReplayHandle>().clone();
+
+        for _ in 0..ticks {
+            world.write_resource::<Time>().advance_fixed();
+            replay.begin_tick(world.read_resource::<Time>().tick());
+            for event in replay.inputs() {
+                input_event_handler.send(event);
+            }
+
+            dispatcher.dispatch(&mut world.res);
+            PrefabSpawner::spawn_queued(world);
+            input_event_handler.end_tick();
+            world.read_resource::<EventBus>().advance();
+            render_dispatcher.dispatch(&mut world.res);
+        }
+    }
+}
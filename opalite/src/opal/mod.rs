@@ -4,5 +4,8 @@ pub use self::builder::{ OpalBuilder, PartialOpalBuilder };
 mod default_systems;
 pub use self::default_systems::{ DefaultSystems };
 
+mod headless;
+pub use self::headless::HeadlessOpal;
+
 mod opal;
-pub use self::opal::{ Gluon, GluonUi, Opal, OpalUi, WindowClosed };
+pub use self::opal::{ Gluon, GluonUi, Opal, OpalUi, UiCapture, WindowClosed };
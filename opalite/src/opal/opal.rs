@@ -1,12 +1,27 @@
-use std::{ cmp::PartialEq, collections::HashMap, ops::{ Deref, DerefMut } };
+use std::{ cmp::PartialEq, collections::{ HashMap, HashSet }, ops::{ Deref, DerefMut }, path::PathBuf, time::Instant };
+use cgmath::Vector3;
+use failure::Error;
 use conrod::{ self, render::OwnedPrimitives, widget::{ Id, Widget }, Ui };
 use gluon;
-use specs::{ Dispatcher, World };
+use specs::{ Dispatcher, Entity, FetchMut, World };
 use winit::{ EventsLoop, Window };
 use crate::{
     Config,
+    EventBus,
     InputEvent,
     InputEventHandler,
+    InputMap,
+    LevelDesc,
+    PrefabSpawner,
+    ReplayFile,
+    ReplayHandle,
+    Resources,
+    RLock,
+    SaveFile,
+    SaveRegistry,
+    Time,
+    load_level,
+    spawn_prefab,
 };
 use crate::gluon_api::conrod::GluonWidget;
 use conrod::{ Positionable, Colorable };
@@ -35,6 +50,29 @@ impl DerefMut for OpalUi {
     }
 }
 
+// whether conrod is using the mouse or keyboard, captured presses aren't sent to the `InputEventHandler`
+// but releases of anything it saw pressed are
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct UiCapture {
+    pub mouse: bool,
+    pub keyboard: bool,
+}
+
+impl UiCapture {
+    fn from_ui(ui: &Ui) -> Self {
+        let input = &ui.global_input().current;
+        // the window itself is under the mouse when no other widget is
+        let over_widget = input.widget_under_mouse
+            .map(|id| id != ui.window)
+            .unwrap_or(false);
+
+        UiCapture {
+            mouse: over_widget || input.widget_capturing_mouse.is_some(),
+            keyboard: input.widget_capturing_keyboard.is_some(),
+        }
+    }
+}
+
 pub struct Gluon {
     pub thread: gluon::RootedThread,
     pub compiler: gluon::Compiler,
@@ -59,6 +97,7 @@ impl DerefMut for GluonUi {
 pub struct Opal<'a, 'b> {
     pub(super) config: Config,
     pub(super) dispatcher: Dispatcher<'a, 'b>,
+    pub(super) render_dispatcher: Dispatcher<'a, 'b>,
     pub(super) events_loop: EventsLoop,
     pub(super) input_event_handler: InputEventHandler,
     pub(super) ui: Ui,
@@ -93,20 +132,133 @@ impl<'a, 'b> Opal<'a, 'b> {
         &mut self.world
     }
 
+    pub fn spawn_prefab(&mut self, name: &str, position: Vector3<i32>) -> Result<Entity, Error> {
+        spawn_prefab(&mut self.world, name, position)
+    }
+
+    // replaces every entity that's on the map, returns the entities the level created
+    pub fn load_level(&mut self, path: &PathBuf) -> Result<Vec<Entity>, Error> {
+        let level = {
+            let resources = self.world.read_resource::<RLock<Resources>>();
+            LevelDesc::load(path, &resources)?
+        };
+        load_level(&mut self.world, level)
+    }
+
+    // register components and `Data` entries here to have them saved
+    pub fn save_registry_mut(&self) -> FetchMut<SaveRegistry> {
+        self.world.write_resource::<SaveRegistry>()
+    }
+
+    pub fn save_game(&self, path: &PathBuf) -> Result<(), Error> {
+        let registry = self.world.read_resource::<SaveRegistry>().clone();
+        registry.save(&self.world)?.save(path)
+    }
+
+    // returns the entities that were loaded
+    pub fn load_game(&mut self, path: &PathBuf) -> Result<Vec<Entity>, Error> {
+        let file = SaveFile::load(path)?;
+        let registry = self.world.read_resource::<SaveRegistry>().clone();
+        registry.load(&mut self.world, file)
+    }
+
+    pub fn input_map(&self) -> &InputMap {
+        self.input_event_handler.input_map()
+    }
+
+    // for rebinding controls, `save_input_map` keeps the changes
+    pub fn input_map_mut(&mut self) -> &mut InputMap {
+        self.input_event_handler.input_map_mut()
+    }
+
+    pub fn save_input_map(&self, path: &PathBuf) -> Result<(), Error> {
+        self.input_event_handler.input_map().save(path)
+    }
+
+    pub fn replay(&self) -> ReplayHandle {
+        self.world.read_resource::<ReplayHandle>().clone()
+    }
+
+    // records input from the next tick on, call `save_replay` to write it out
+    pub fn record_replay(&mut self) {
+        let next_tick = self.world.read_resource::<Time>().tick() + 1;
+        self.replay().record(next_tick);
+    }
+
+    pub fn save_replay(&self, path: &PathBuf) -> Result<(), Error> {
+        self.replay().save(path)
+    }
+
+    // live input is ignored until the replay has finished
+    pub fn play_replay(&mut self, path: &PathBuf) -> Result<(), Error> {
+        let file = ReplayFile::load(path)?;
+        let next_tick = self.world.read_resource::<Time>().tick() + 1;
+        self.replay().play(file, next_tick);
+        Ok(())
+    }
+
     pub fn run(&mut self) -> Result<(), ()> {
-        use winit::{ Event, WindowEvent };
+        use winit::{ ElementState, Event, WindowEvent };
 
-        let Opal { dispatcher, events_loop, input_event_handler, ui, window, world, .. } = self;
+        let Opal { dispatcher, render_dispatcher, events_loop, input_event_handler, ui, window, world, .. } = self;
         let mut name_to_ui = HashMap::new();
+        let mut last_frame = Instant::now();
+        let replay = world.read_resource::<ReplayHandle>().clone();
+        // what the handler saw go down, so that it sees them go up even if conrod has captured input since
+        let mut held_buttons = HashSet::new();
+        let mut held_keys = HashSet::new();
+        // the window's size while a replay was playing, sent once it has finished
+        let mut live_size = None;
 
         while *world.read_resource::<WindowClosed>() == false {
+            // input is handled by the next tick
+            replay.begin_tick(world.read_resource::<Time>().tick() + 1);
+            let playing = replay.is_playing();
+
+            if !playing {
+                if let Some((width, height)) = live_size.take() {
+                    input_event_handler.send(InputEvent::Resized { width, height });
+                }
+            }
+
             events_loop.poll_events(|event| {
+                match conrod::backend::winit::convert_event(event.clone(), window) {
+                    Some(event) => ui.handle_event(event),
+                    None => (),
+                };
+
+                let capture = UiCapture::from_ui(ui);
+                *world.write_resource::<UiCapture>() = capture;
+
                 if let Event::WindowEvent { event, .. } = event.clone() {
                     match event {
                         WindowEvent::Closed => {
                             let mut window_closed = world.write_resource::<WindowClosed>();
                             *window_closed = WindowClosed(true);
                         },
+                        // the replay has the window events it was recorded with
+                        WindowEvent::Resized(width, height) if playing => {
+                            live_size = Some((width, height));
+                        },
+                        _ if playing => (),
+                        WindowEvent::Resized(width, height) => {
+                            input_event_handler.send(InputEvent::Resized { width, height });
+                        },
+                        WindowEvent::Focused(focused) => {
+                            // the handler lets go of everything, and releases won't arrive while unfocused
+                            if !focused {
+                                held_buttons.clear();
+                                held_keys.clear();
+                            }
+                            input_event_handler.send(InputEvent::Focused { focused });
+                        },
+                        // cursor movement always goes through so the handler knows where the mouse is
+                        WindowEvent::MouseWheel { .. } if capture.mouse => (),
+                        WindowEvent::ReceivedCharacter(_) if capture.keyboard => (),
+                        WindowEvent::MouseInput { state: ElementState::Pressed, .. } if capture.mouse => (),
+                        WindowEvent::KeyboardInput { input, .. } if input.state == ElementState::Pressed && capture.keyboard => (),
+                        WindowEvent::MouseInput { state: ElementState::Released, button, .. } if !held_buttons.contains(&button) => (),
+                        WindowEvent::KeyboardInput { input, .. } if input.state == ElementState::Released && !held_keys.contains(&input.scancode) => (),
                         WindowEvent::CursorMoved { position, .. } => {
                             input_event_handler.send(InputEvent::MouseCoordinates {
                                 x: position.0,
@@ -114,18 +266,35 @@ impl<'a, 'b> Opal<'a, 'b> {
                             });
                         },
                         WindowEvent::MouseInput { state, button, .. } => {
+                            match state {
+                                ElementState::Pressed => held_buttons.insert(button),
+                                ElementState::Released => held_buttons.remove(&button),
+                            };
                             input_event_handler.send(InputEvent::MouseClicked {
                                 state, button
                             });
-                        }
+                        },
+                        WindowEvent::MouseWheel { delta, .. } => {
+                            input_event_handler.send(InputEvent::MouseScrolled { delta });
+                        },
+                        WindowEvent::KeyboardInput { input, .. } => {
+                            match input.state {
+                                ElementState::Pressed => held_keys.insert(input.scancode),
+                                ElementState::Released => held_keys.remove(&input.scancode),
+                            };
+                            input_event_handler.send(InputEvent::Keyboard {
+                                state: input.state,
+                                scancode: input.scancode,
+                                virtual_keycode: input.virtual_keycode,
+                                modifiers: input.modifiers,
+                            });
+                        },
+                        WindowEvent::ReceivedCharacter(character) => {
+                            input_event_handler.send(InputEvent::Text { character });
+                        },
                         _ => (),
                     }
                 }
-
-                match conrod::backend::winit::convert_event(event.clone(), window) {
-                    Some(event) => ui.handle_event(event),
-                    None => (),
-                };
             });
 
             {
@@ -156,12 +325,31 @@ impl<'a, 'b> Opal<'a, 'b> {
                 }
             }
 
+            {
+                let now = Instant::now();
+                world.write_resource::<Time>().advance_frame(now.duration_since(last_frame));
+                last_frame = now;
+            }
+
+            // the simulation runs at `Config::tick_rate` however fast frames are rendered
+            while world.write_resource::<Time>().advance_tick() {
+                replay.begin_tick(world.read_resource::<Time>().tick());
+                for event in replay.inputs() {
+                    input_event_handler.send(event);
+                }
+
+                dispatcher.dispatch(&mut world.res);
+                PrefabSpawner::spawn_queued(world);
+                input_event_handler.end_tick();
+                world.read_resource::<EventBus>().advance();
+            }
+
             {
                 let mut opal_ui = world.write_resource::<OpalUi>();
                 *opal_ui = OpalUi(Some(ui.draw().owned()));
             }
 
-            dispatcher.dispatch(&mut world.res);
+            render_dispatcher.dispatch(&mut world.res);
         }
 
         Ok(())
@@ -1,109 +1,389 @@
-use std::cmp::{ Ordering, PartialOrd };
-use cgmath::{ prelude::*, Vector3, Vector4 };
+use std::{ cmp::{ Ordering, PartialOrd }, collections::HashMap, sync::Arc };
+use cgmath::{ prelude::*, Matrix4, Vector3, Vector4 };
 use winit::{ ElementState, MouseButton };
-use specs::{ Entities, Fetch, System, ReadStorage };
+use specs::{ Entities, Entity, Fetch, System, ReadStorage };
 use crate::{
+    renderer::world_position,
     Camera,
     CollisionLayers,
+    EventBus,
     InputEvent,
+    Interpolation,
     Map,
+    Message,
     MessageQueue,
     MessageSender,
     MessageReceiver,
+    ModelBounds,
+    ModelData,
+    ModelKey,
+    ModelType,
     Position,
+    Resources,
     RLock,
     Shard,
+    Time,
+    WLock,
 };
 
+// published on the `EventBus` for every mouse press
+#[derive(Clone, Debug, PartialEq)]
+pub enum PickEvent {
+    EntityPicked { entity: Entity, hit_point: Vector3<f32>, distance: f32, button: MouseButton },
+    // the cell of the picked entity, or the cell under the cursor on the ground if nothing was hit
+    CellPicked { cell: Vector3<i32> },
+}
+
+impl Message for PickEvent { }
+
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    hovered: Option<Entity>,
+    selected: Option<Entity>,
+    cell: Option<Vector3<i32>>,
+}
+
+impl Selection {
+    // the entity under the cursor
+    pub fn hovered(&self) -> Option<Entity> {
+        self.hovered
+    }
+
+    // the last entity clicked with the left mouse button
+    pub fn selected(&self) -> Option<Entity> {
+        self.selected
+    }
+
+    // the last cell clicked with the left mouse button
+    pub fn cell(&self) -> Option<Vector3<i32>> {
+        self.cell
+    }
+}
+
+struct Hit {
+    entity: Entity,
+    point: Vector3<f32>,
+    distance: f32,
+}
+
 pub struct PickerSystem {
     receiver: MessageReceiver<InputEvent>,
     sender: MessageSender<InputEvent>,
+    selection: WLock<Selection>,
+    resources: RLock<Resources>,
+    // `None` for models that couldn't be loaded
+    bounds: HashMap<ModelKey, Option<Arc<ModelBounds>>>,
+    pick_triangles: bool,
     width: u32,
     height: u32,
 }
 
 impl PickerSystem {
-    pub fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32, resources: RLock<Resources>) -> Self {
         let (sender, receiver) = MessageQueue::new();
+        let selection = WLock::new(Selection::default());
 
-        Self { sender, receiver, width, height }
+        Self {
+            sender,
+            receiver,
+            selection,
+            resources,
+            bounds: HashMap::new(),
+            pick_triangles: false,
+            width,
+            height,
+        }
     }
-}
 
-impl<'a> Shard<'a> for PickerSystem {
-    type Message = InputEvent;
+    pub fn selection(&self) -> RLock<Selection> {
+        self.selection.get_reader()
+    }
 
-    fn sender(&self) -> MessageSender<Self::Message> {
-        self.sender.clone()
+    // test the triangles of a model after its bounding box
+    pub fn set_pick_triangles(&mut self, pick_triangles: bool) {
+        self.pick_triangles = pick_triangles;
     }
-}
 
-impl<'a> System<'a> for PickerSystem {
-    type SystemData = (Fetch<'a, Camera>, Entities<'a>, ReadStorage<'a, Position>, Fetch<'a, RLock<Map>>, ReadStorage<'a, CollisionLayers>);
+    // the ray through the cursor at `x`, `y`, in world space
+    fn ray(&self, camera: &Camera, x: f64, y: f64) -> (Vector3<f32>, Vector3<f32>) {
+        let x = ((x as f32 * 2.0) / (self.width as f32)) - 1.0;
+        let y = ((y as f32 * 2.0) / (self.height as f32)) - 1.0;
+
+        let z = 1.0;
+        let w = 1.0;
+        let ray_clip = Vector4::new(x, y, z, w);
 
-    fn run(&mut self, (camera, entities, positions, map, _collision_layers): Self::SystemData) {
+        let proj_i = camera.projection(self.width as f32 / self.height as f32).invert().unwrap();
+        let ray_eye = proj_i * ray_clip;
+        let ray_eye = Vector4::new(ray_eye.x, ray_eye.y, -1.0, 0.0);
+
+        let view_i = camera.view().invert().unwrap();
+        let ray_world = (view_i * ray_eye).xyz();
+
+        (camera.position, ray_world.normalize())
+    }
+
+    // loaded the first time a model is picked, procedural models are asked every time
+    fn bounds(&mut self, key: &ModelKey) -> Option<Arc<ModelBounds>> {
+        if let ModelType::Procedural(procedural) = key.ty() {
+            return procedural.lock().unwrap().bounds().map(Arc::new);
+        }
+
+        let resources = &self.resources;
+        self.bounds.entry(key.clone())
+            .or_insert_with(|| match ModelBounds::from_key(key, resources) {
+                Ok(bounds) => bounds.map(Arc::new),
+                Err(err) => {
+                    println!("Picking Error: {}", err);
+                    None
+                },
+            })
+            .clone()
+    }
+
+    // the closest entity in front of the camera along the ray, entities without a model are
+    // picked as a unit sphere
+    fn pick<'a>(
+        &mut self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        map: &Map,
+        entities: &Entities<'a>,
+        positions: &ReadStorage<'a, Position>,
+        model_keys: &ReadStorage<'a, ModelKey>,
+        model_datas: &ReadStorage<'a, ModelData>,
+        interpolations: &ReadStorage<'a, Interpolation>,
+        alpha: f32,
+    ) -> Option<Hit> {
         use specs::Join;
 
-        let map = map.read().unwrap();
+        let mut intersections = vec![];
+        for (entity, _) in (&**entities, positions).join() {
+            if map.location(&entity).is_none() {
+                continue;
+            }
+
+            // where the renderer draws it
+            let position = world_position(map, interpolations, alpha, entity);
+            let bounds = model_keys.get(entity).and_then(|key| self.bounds(key));
+
+            let distance = match bounds {
+                Some(bounds) => {
+                    let model_data = match model_datas.get(entity) {
+                        Some(data) => *data,
+                        None => Default::default(),
+                    };
 
-        for message in self.receiver.messages() {
-            let (state, button, x, y) = match message {
-                InputEvent::MouseClickedWithCoordinates {
-                    state, button, x, y
-                } => (state, button, x, y),
-                _ => continue,
+                    intersect_bounds(origin, direction, &bounds, model_data.to_matrix(&position), self.pick_triangles)
+                },
+                None => intersect_sphere(origin, direction, position),
             };
 
-            if state != ElementState::Pressed {
-                continue;
+            if let Some(distance) = distance {
+                intersections.push(Hit {
+                    entity,
+                    point: origin + direction * distance,
+                    distance,
+                });
             }
+        }
 
-            if button != MouseButton::Left {
-                continue;
+        intersections.into_iter()
+            .min_by(|a, b| nearest(&a.distance, &b.distance))
+    }
+}
+
+fn nearest(a: &f32, b: &f32) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Greater)
+}
+
+// the ray is moved into model space, it isn't normalized afterwards so that distances
+// along it are the same as along the world space ray
+fn intersect_bounds(origin: Vector3<f32>, direction: Vector3<f32>, bounds: &ModelBounds, model: Matrix4<f32>, pick_triangles: bool) -> Option<f32> {
+    let model_i = model.invert()?;
+    let origin = (model_i * origin.extend(1.0)).truncate();
+    let direction = (model_i * direction.extend(0.0)).truncate();
+
+    let distance = intersect_aabb(origin, direction, bounds.min, bounds.max)?;
+
+    if !pick_triangles {
+        return Some(distance);
+    }
+
+    bounds.triangles.iter()
+        .filter_map(|triangle| intersect_triangle(origin, direction, triangle))
+        .min_by(nearest)
+}
+
+// slab test, the far side is used when the ray starts inside the box
+fn intersect_aabb(origin: Vector3<f32>, direction: Vector3<f32>, min: Vector3<f32>, max: Vector3<f32>) -> Option<f32> {
+    let mut near = ::std::f32::NEG_INFINITY;
+    let mut far = ::std::f32::INFINITY;
+
+    for axis in 0..3 {
+        if direction[axis].abs() < ::std::f32::EPSILON {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
             }
+            continue;
+        }
 
-            let x = ((x as f32 * 2.0) / (self.width as f32)) - 1.0;
-            let y = ((y as f32 * 2.0) / (self.height as f32)) - 1.0;
+        let t_1 = (min[axis] - origin[axis]) / direction[axis];
+        let t_2 = (max[axis] - origin[axis]) / direction[axis];
 
-            let z = 1.0;
-            let w = 1.0;
-            let ray_clip = Vector4::new(x, y, z, w);
+        near = near.max(t_1.min(t_2));
+        far = far.min(t_1.max(t_2));
+    }
 
-            let proj_i = camera.projection(self.width as f32 / self.height as f32).invert().unwrap();
-            let ray_eye = proj_i * ray_clip;
-            let ray_eye = Vector4::new(ray_eye.x, ray_eye.y, -1.0, 0.0);
+    if near > far || far < 0.0 {
+        None
+    } else if near >= 0.0 {
+        Some(near)
+    } else {
+        Some(far)
+    }
+}
 
-            let view_i = camera.view().invert().unwrap();
-            let ray_world = (view_i * ray_eye).xyz();
+// Möller–Trumbore, both sides of the triangle are hit
+fn intersect_triangle(origin: Vector3<f32>, direction: Vector3<f32>, triangle: &[Vector3<f32>; 3]) -> Option<f32> {
+    let edge_1 = triangle[1] - triangle[0];
+    let edge_2 = triangle[2] - triangle[0];
+
+    let p = direction.cross(edge_2);
+    let det = edge_1.dot(p);
+
+    if relative_eq!(det, 0.0) {
+        return None;
+    }
 
-            let ray_world = ray_world.normalize();
-            let ray_origin = camera.position;
+    let s = origin - triangle[0];
+    let u = s.dot(p) / det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
 
-            let mut intersections = vec![];
-            for (entity, _) in (&*entities, &positions).join() {
-                let position = match map.location(&entity) {
-                    Some(position) => Vector3::new(position.x as f32, position.y as f32, position.z as f32),
-                    None => continue,
-                };
+    let q = s.cross(edge_1);
+    let v = direction.dot(q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
 
-                let l = ray_origin - position;
-                let a = ray_world.dot(ray_world);
-                let b = 2.0 * ray_world.dot(l);
-                let c = l.dot(l) - 1.0;
+    let t = edge_2.dot(q) / det;
+    if t >= 0.0 { Some(t) } else { None }
+}
 
-                if solve_quadratic_roots(a, b, c) == 0 {
-                    continue;
-                }
+fn intersect_sphere(origin: Vector3<f32>, direction: Vector3<f32>, center: Vector3<f32>) -> Option<f32> {
+    let l = origin - center;
+    let a = direction.dot(direction);
+    let b = 2.0 * direction.dot(l);
+    let c = l.dot(l) - 1.0;
 
-                let (root, _) = solve_quadratic(a, b, c);
-                let root = root.unwrap();
-                intersections.push((root, entity));
+    if solve_quadratic_roots(a, b, c) == 0 {
+        return None;
+    }
 
-                println!("{:?}", position);
-            }
+    match solve_quadratic(a, b, c) {
+        (Some(near), _) if near >= 0.0 => Some(near),
+        (_, Some(far)) if far >= 0.0 => Some(far),
+        _ => None,
+    }
+}
 
-            intersections.sort_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap_or(Ordering::Greater));
-            println!("{:?}", intersections);
+// the cell on the bottom layer closest to where the ray meets the ground
+fn ground_cell(origin: Vector3<f32>, direction: Vector3<f32>, map: &Map) -> Option<Vector3<i32>> {
+    if direction.y.abs() < ::std::f32::EPSILON {
+        return None;
+    }
+
+    let t = -origin.y / direction.y;
+    if t < 0.0 {
+        return None;
+    }
+
+    let point = origin + direction * t;
+    let dimensions = map.dimensions();
+    let distance = |cell: &Vector3<i32>| {
+        let position = map.world_position(cell);
+        (position.x - point.x).powi(2) + (position.z - point.z).powi(2)
+    };
+
+    (0..=dimensions.x)
+        .flat_map(|column| (0..=dimensions.z).map(move |z| map.from_column(column, 0, z)))
+        .min_by(|a, b| nearest(&distance(a), &distance(b)))
+}
+
+impl<'a> Shard<'a> for PickerSystem {
+    type Message = InputEvent;
+
+    fn sender(&self) -> MessageSender<Self::Message> {
+        self.sender.clone()
+    }
+}
+
+impl<'a> System<'a> for PickerSystem {
+    type SystemData = (
+        Fetch<'a, Camera>,
+        Entities<'a>,
+        ReadStorage<'a, Position>,
+        Fetch<'a, RLock<Map>>,
+        ReadStorage<'a, CollisionLayers>,
+        Fetch<'a, EventBus>,
+        ReadStorage<'a, ModelKey>,
+        ReadStorage<'a, ModelData>,
+        ReadStorage<'a, Interpolation>,
+        Fetch<'a, Time>,
+    );
+
+    fn run(&mut self, (camera, entities, positions, map, _collision_layers, events, model_keys, model_datas, interpolations, time): Self::SystemData) {
+        let map = map.read().unwrap();
+        let messages = self.receiver.messages().collect::<Vec<_>>();
+
+        for message in messages {
+            match message {
+                InputEvent::Resized { width, height } => {
+                    self.width = width;
+                    self.height = height;
+                },
+                InputEvent::MouseCoordinates { x, y } => {
+                    let (origin, direction) = self.ray(&camera, x, y);
+                    let hit = self.pick(origin, direction, &map, &entities, &positions, &model_keys, &model_datas, &interpolations, time.alpha());
+
+                    self.selection.write().unwrap().hovered = hit.map(|hit| hit.entity);
+                },
+                InputEvent::MouseClickedWithCoordinates { state, button, x, y } => {
+                    if state != ElementState::Pressed {
+                        continue;
+                    }
+
+                    let (origin, direction) = self.ray(&camera, x, y);
+                    let hit = self.pick(origin, direction, &map, &entities, &positions, &model_keys, &model_datas, &interpolations, time.alpha());
+
+                    let cell = match &hit {
+                        Some(hit) => map.location(&hit.entity).cloned(),
+                        None => ground_cell(origin, direction, &map),
+                    };
+
+                    if let Some(hit) = &hit {
+                        events.publish(PickEvent::EntityPicked {
+                            entity: hit.entity,
+                            hit_point: hit.point,
+                            distance: hit.distance,
+                            button,
+                        });
+                    }
+
+                    if let Some(cell) = cell {
+                        events.publish(PickEvent::CellPicked { cell });
+                    }
+
+                    if button == MouseButton::Left {
+                        let mut selection = self.selection.write().unwrap();
+                        selection.selected = hit.map(|hit| hit.entity);
+                        selection.cell = cell;
+                    }
+                },
+                _ => (),
+            }
         }
     }
 }
@@ -143,3 +423,48 @@ fn solve_quadratic(a: f32, b: f32, c: f32) -> (Option<f32>, Option<f32>) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_hit_the_near_side_of_a_box() {
+        let min = Vector3::new(-1.0, -1.0, -1.0);
+        let max = Vector3::new(1.0, 1.0, 1.0);
+
+        let t = intersect_aabb(Vector3::new(0.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0), min, max);
+        assert_relative_eq!(t.unwrap(), 4.0);
+
+        // from inside, the hit is where the ray leaves the box
+        let t = intersect_aabb(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), min, max);
+        assert_relative_eq!(t.unwrap(), 1.0);
+    }
+
+    #[test]
+    fn rays_miss_boxes_beside_or_behind_them() {
+        let min = Vector3::new(-1.0, -1.0, -1.0);
+        let max = Vector3::new(1.0, 1.0, 1.0);
+
+        assert_eq!(intersect_aabb(Vector3::new(3.0, 0.0, -5.0), Vector3::new(0.0, 0.0, 1.0), min, max), None);
+        assert_eq!(intersect_aabb(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 1.0), min, max), None);
+    }
+
+    #[test]
+    fn rays_hit_triangles_inside_their_edges() {
+        let triangle = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        ];
+        let down = Vector3::new(0.0, 0.0, -1.0);
+
+        let t = intersect_triangle(Vector3::new(0.25, 0.25, 2.0), down, &triangle);
+        assert_relative_eq!(t.unwrap(), 2.0);
+
+        assert_eq!(intersect_triangle(Vector3::new(0.75, 0.75, 2.0), down, &triangle), None);
+        assert_eq!(intersect_triangle(Vector3::new(0.25, 0.25, -2.0), down, &triangle), None);
+        // parallel to the triangle
+        assert_eq!(intersect_triangle(Vector3::new(0.25, 0.25, 2.0), Vector3::new(1.0, 0.0, 0.0), &triangle), None);
+    }
+}
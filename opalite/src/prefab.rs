@@ -0,0 +1,339 @@
+use std::{ collections::{ BTreeMap, HashMap, HashSet }, path::PathBuf, sync::{ Arc, Mutex } };
+use cgmath::Vector3;
+use failure::Error;
+use ron;
+use specs::{ Entity, World };
+use crate::{
+    AiComponent,
+    Behaviour,
+    BehaviourTree,
+    CollisionLayers,
+    InitialPosition,
+    Light,
+    MaterialDesc,
+    ModelData,
+    ModelKey,
+    Resources,
+    RLock,
+    SavedModel,
+    SaveRegistry,
+};
+use crate::save::ParsedValue;
+
+// every field left out is taken from `parent`, `data` entries are merged with the parent's
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct PrefabDesc {
+    pub parent: Option<String>,
+    pub model: Option<SavedModel>,
+    pub material: Option<MaterialDesc>,
+    pub model_data: Option<ModelData>,
+    pub light: Option<Light>,
+    pub collision_layers: Option<CollisionLayers>,
+    pub behaviour: Option<Behaviour>,
+    // values are loaded through the `Data` types registered with the `SaveRegistry`
+    pub data: BTreeMap<String, String>,
+}
+
+impl PrefabDesc {
+    // `self` overrides `parent`
+    fn inherit(self, parent: PrefabDesc) -> PrefabDesc {
+        let mut data = parent.data;
+        data.extend(self.data);
+
+        PrefabDesc {
+            parent: parent.parent,
+            model: self.model.or(parent.model),
+            material: self.material.or(parent.material),
+            model_data: self.model_data.or(parent.model_data),
+            light: self.light.or(parent.light),
+            collision_layers: self.collision_layers.or(parent.collision_layers),
+            behaviour: self.behaviour.or(parent.behaviour),
+            data,
+        }
+    }
+}
+
+pub struct PrefabLibrary {
+    prefabs: HashMap<String, PrefabDesc>,
+    // instances of a prefab share a model
+    models: HashMap<String, ModelKey>,
+}
+
+impl PrefabLibrary {
+    pub fn new() -> Self {
+        Self {
+            prefabs: HashMap::new(),
+            models: HashMap::new(),
+        }
+    }
+
+    // each file is a map of prefab names to `PrefabDesc`s, later files override earlier ones
+    pub fn load(paths: &[PathBuf], resources: &RLock<Resources>) -> Result<Self, Error> {
+        let mut library = PrefabLibrary::new();
+
+        for path in paths {
+            let resources = resources.read().unwrap();
+            let desc = resources.get_string(path)?;
+            let prefabs = ron::de::from_str::<BTreeMap<String, PrefabDesc>>(&desc)?;
+
+            for (name, prefab) in prefabs {
+                library.insert(name, prefab);
+            }
+        }
+
+        Ok(library)
+    }
+
+    pub fn insert<S: Into<String>>(&mut self, name: S, prefab: PrefabDesc) {
+        let name = name.into();
+        self.models.remove(&name);
+        self.prefabs.insert(name, prefab);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.prefabs.contains_key(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.prefabs.keys().cloned().collect()
+    }
+
+    // the prefab with everything it inherits filled in
+    pub fn resolve(&self, name: &str) -> Result<PrefabDesc, Error> {
+        let mut visited = HashSet::new();
+        let mut next = Some(name.to_owned());
+        let mut resolved: Option<PrefabDesc> = None;
+
+        while let Some(name) = next {
+            if !visited.insert(name.clone()) {
+                bail!("Prefab '{}' inherits from itself.", name);
+            }
+
+            let prefab = match self.prefabs.get(&name) {
+                Some(prefab) => prefab.clone(),
+                None => bail!("Prefab '{}' doesn't exist.", name),
+            };
+
+            next = prefab.parent.clone();
+            resolved = Some(match resolved {
+                Some(child) => child.inherit(prefab),
+                None => prefab,
+            });
+        }
+
+        Ok(resolved.unwrap())
+    }
+
+    fn model(&mut self, name: &str, prefab: &PrefabDesc) -> Option<ModelKey> {
+        let model = prefab.model.clone()?;
+        Some(self.models.entry(name.to_owned()).or_insert_with(|| model.to_key()).clone())
+    }
+}
+
+impl Default for PrefabLibrary {
+    fn default() -> Self {
+        PrefabLibrary::new()
+    }
+}
+
+// the entity is put on the map at `position` the next time `MapSystem` runs
+pub fn spawn_prefab(world: &mut World, name: &str, position: Vector3<i32>) -> Result<Entity, Error> {
+    spawn_prefab_with(world, name, PrefabDesc::default(), position)
+}
+
+// anything set in `overrides` replaces what the prefab has, its `parent` is ignored
+pub fn spawn_prefab_with(world: &mut World, name: &str, overrides: PrefabDesc, position: Vector3<i32>) -> Result<Entity, Error> {
+    let prefab = ResolvedPrefab::new(world, name, overrides)?;
+    Ok(prefab.spawn(world, position))
+}
+
+// a prefab with its overrides applied and its `data` parsed, spawning it can't fail
+pub(crate) struct ResolvedPrefab {
+    prefab: PrefabDesc,
+    model: Option<ModelKey>,
+    data: Vec<ParsedValue>,
+}
+
+impl ResolvedPrefab {
+    pub(crate) fn new(world: &World, name: &str, overrides: PrefabDesc) -> Result<Self, Error> {
+        let (prefab, model) = {
+            let mut library = world.write_resource::<PrefabLibrary>();
+            let prefab = library.resolve(name)?;
+            let model = match overrides.model.clone() {
+                Some(model) => Some(model.to_key()),
+                None => library.model(name, &prefab),
+            };
+            (overrides.inherit(prefab), model)
+        };
+
+        let registry = world.read_resource::<SaveRegistry>();
+        let mut data = vec![];
+
+        for (name, value) in &prefab.data {
+            data.push(registry.parse_data(name, value)?);
+        }
+
+        Ok(Self {
+            prefab,
+            model,
+            data,
+        })
+    }
+
+    pub(crate) fn spawn(self, world: &mut World, position: Vector3<i32>) -> Entity {
+        let ResolvedPrefab { prefab, model, data } = self;
+        let PrefabDesc { material, model_data, light, collision_layers, behaviour, .. } = prefab;
+
+        let mut builder = world.create_entity()
+            .with(InitialPosition(position));
+
+        if let Some(model) = model {
+            builder = builder.with(model);
+        }
+
+        if let Some(material) = material {
+            builder = builder.with(material);
+        }
+
+        if let Some(model_data) = model_data {
+            builder = builder.with(model_data);
+        }
+
+        if let Some(light) = light {
+            builder = builder.with(light);
+        }
+
+        if let Some(collision_layers) = collision_layers {
+            builder = builder.with(collision_layers);
+        }
+
+        if let Some(behaviour) = behaviour {
+            builder = builder.with(AiComponent::from_behaviour(BehaviourTree::new(behaviour)));
+        }
+
+        let entity = builder.build();
+
+        for mut value in data {
+            value(world, entity);
+        }
+
+        entity
+    }
+}
+
+// prefabs requested from Gluon, they're spawned after each tick
+#[derive(Clone, Debug)]
+pub struct PrefabSpawner(Arc<Mutex<Vec<(String, Vector3<i32>)>>>);
+
+impl PrefabSpawner {
+    pub fn new() -> Self {
+        PrefabSpawner(Arc::new(Mutex::new(vec![])))
+    }
+
+    pub fn spawn<S: Into<String>>(&self, name: S, position: Vector3<i32>) {
+        self.0.lock().unwrap().push((name.into(), position));
+    }
+
+    pub(crate) fn spawn_queued(world: &mut World) {
+        let queued = {
+            let spawner = world.read_resource::<PrefabSpawner>();
+            let mut queued = spawner.0.lock().unwrap();
+            queued.drain(..).collect::<Vec<_>>()
+        };
+
+        for (name, position) in queued {
+            if let Err(err) = spawn_prefab(world, &name, position) {
+                println!("Prefab Error: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::GoalDesc;
+    use super::*;
+
+    fn library(desc: &str) -> PrefabLibrary {
+        let mut library = PrefabLibrary::new();
+
+        for (name, prefab) in ron::de::from_str::<BTreeMap<String, PrefabDesc>>(desc).unwrap() {
+            library.insert(name, prefab);
+        }
+
+        library
+    }
+
+    fn data(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn prefabs_inherit_from_their_parents() {
+        let library = library(r#"{
+            "creature": (
+                behaviour: Some(Goal(Wait(5))),
+                data: { "Health": "10", "Faction": "\"wild\"" },
+            ),
+            "goblin": (
+                parent: Some("creature"),
+                data: { "Health": "5" },
+            ),
+            "goblin_chief": (
+                parent: Some("goblin"),
+                behaviour: Some(Goal(Wait(1))),
+            ),
+        }"#);
+
+        let goblin = library.resolve("goblin").unwrap();
+        assert_eq!(goblin.parent, None);
+        assert_eq!(goblin.behaviour, Some(Behaviour::Goal(GoalDesc::Wait(5))));
+        assert_eq!(goblin.data, data(&[("Faction", "\"wild\""), ("Health", "5")]));
+
+        let chief = library.resolve("goblin_chief").unwrap();
+        assert_eq!(chief.behaviour, Some(Behaviour::Goal(GoalDesc::Wait(1))));
+        assert_eq!(chief.data, goblin.data);
+    }
+
+    #[test]
+    fn overrides_replace_what_the_prefab_has() {
+        let library = library(r#"{
+            "goblin": (
+                behaviour: Some(Goal(Wait(5))),
+                data: { "Health": "10", "Faction": "\"wild\"" },
+            ),
+        }"#);
+
+        let overrides = PrefabDesc {
+            parent: Some("ignored".to_owned()),
+            behaviour: Some(Behaviour::Goal(GoalDesc::Wait(1))),
+            data: data(&[("Health", "1")]),
+            .. Default::default()
+        };
+        let prefab = overrides.inherit(library.resolve("goblin").unwrap());
+
+        assert_eq!(prefab.parent, None);
+        assert_eq!(prefab.behaviour, Some(Behaviour::Goal(GoalDesc::Wait(1))));
+        assert_eq!(prefab.data, data(&[("Faction", "\"wild\""), ("Health", "1")]));
+    }
+
+    #[test]
+    fn cycles_and_unknown_parents_are_errors() {
+        let library = library(r#"{
+            "chicken": (parent: Some("egg")),
+            "egg": (parent: Some("chicken")),
+            "orphan": (parent: Some("missing")),
+        }"#);
+
+        let cycle = library.resolve("chicken").err().unwrap();
+        assert_eq!(cycle.to_string(), "Prefab 'chicken' inherits from itself.");
+
+        let orphan = library.resolve("orphan").err().unwrap();
+        assert_eq!(orphan.to_string(), "Prefab 'missing' doesn't exist.");
+
+        assert!(library.resolve("nothing").is_err());
+    }
+}
@@ -38,7 +38,7 @@ impl<B: Backend> Sampler<B> {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ImageKey(pub String);
 
 pub struct Image<B: Backend> {
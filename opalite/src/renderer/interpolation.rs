@@ -0,0 +1,125 @@
+use cgmath::{ prelude::*, Vector3 };
+use specs::{ Entities, Fetch, System, WriteStorage };
+use crate::{ Map, RLock, Time };
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Default for Easing {
+    fn default() -> Self {
+        Easing::Linear
+    }
+}
+
+impl Easing {
+    // `t` is clamped to 0..1
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.max(0.0).min(1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => if t < 0.5 {
+                2.0 * t * t
+            } else {
+                -1.0 + (4.0 - 2.0 * t) * t
+            },
+        }
+    }
+}
+
+// smooths the rendered position of an entity between the cells it moves through,
+// the entity's `Map` location is still what the game logic uses
+#[derive(Component, Clone, Debug, PartialEq)]
+pub struct Interpolation {
+    // in seconds
+    pub duration: f32,
+    pub easing: Easing,
+    from: Option<Vector3<f32>>,
+    to: Option<Vector3<f32>>,
+    // `Time::tick_time` when the entity started moving towards `to`
+    started: f64,
+    elapsed: f32,
+    current: Option<Vector3<f32>>,
+}
+
+impl Interpolation {
+    pub fn new(duration: f32, easing: Easing) -> Self {
+        Self {
+            duration,
+            easing,
+            from: None,
+            to: None,
+            started: 0.0,
+            elapsed: 0.0,
+            current: None,
+        }
+    }
+
+    // `None` until the entity has been placed on the map
+    pub fn position(&self) -> Option<Vector3<f32>> {
+        self.current
+    }
+
+    pub fn is_moving(&self) -> bool {
+        self.elapsed < self.duration && self.from != self.to
+    }
+
+    // `now` is `Time::tick_time`, so that tweens follow the simulation rather than the frame rate
+    fn update(&mut self, target: Vector3<f32>, now: f64) {
+        if self.to != Some(target) {
+            // start from wherever the entity was drawn last, so that a move mid-tween doesn't jump
+            self.from = Some(self.current.unwrap_or(target));
+            self.to = Some(target);
+            self.started = now;
+        }
+
+        self.elapsed = (now - self.started).max(0.0) as f32;
+
+        let t = if self.duration > 0.0 {
+            self.easing.apply(self.elapsed / self.duration)
+        } else {
+            1.0
+        };
+
+        let from = self.from.unwrap_or(target);
+        self.current = Some(from.lerp(target, t));
+    }
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::new(0.2, Easing::EaseInOut)
+    }
+}
+
+// runs once per rendered frame rather than once per tick
+pub struct InterpolationSystem;
+
+impl InterpolationSystem {
+    pub fn new() -> Self {
+        InterpolationSystem
+    }
+}
+
+impl<'a> System<'a> for InterpolationSystem {
+    type SystemData = (Entities<'a>, WriteStorage<'a, Interpolation>, Fetch<'a, RLock<Map>>, Fetch<'a, Time>);
+
+    fn run(&mut self, (entities, mut interpolations, map, time): Self::SystemData) {
+        use specs::Join;
+
+        let map = map.read().unwrap();
+
+        for (entity, interpolation) in (&*entities, &mut interpolations).join() {
+            if let Some(location) = map.location(&entity) {
+                interpolation.update(map.world_position(location), time.tick_time());
+            }
+        }
+    }
+}
@@ -7,13 +7,13 @@ use ordered_float::NotNaN;
 use hal::{ pso, Backend, DescriptorPool, Device };
 use back::Backend as B;
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum LightType {
     None,
     Point,
 }
 
-#[derive(Debug, Component, Clone)]
+#[derive(Serialize, Deserialize, Debug, Component, Clone)]
 pub struct Light {
     pub ty: LightType,
     pub color: Vector3<f32>,
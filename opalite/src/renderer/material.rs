@@ -7,13 +7,13 @@ use hal::{ pso, Backend, DescriptorPool, Device };
 use hal::pso::{ PipelineStage, ShaderStageFlags };
 use back::Backend as B;
 
-#[derive(Debug, PartialEq, Eq, Clone, Hash)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Hash)]
 pub enum SurfaceType {
     Color([NotNaN<f32>; 4]),
     Texture(ImageKey),
 }
 
-#[derive(Debug, Component, PartialEq, Eq, Clone, Hash)]
+#[derive(Serialize, Deserialize, Debug, Component, PartialEq, Eq, Clone, Hash)]
 pub struct MaterialDesc {
     pub diffuse: SurfaceType,
     pub specular: NotNaN<f32>,
@@ -1,9 +1,9 @@
 use std::sync::{ Arc, Mutex };
 use cgmath::{ prelude::*, Vector3 };
 use failure::Error;
-use specs::{ Entities, Fetch, FetchMut, ReadStorage, System, WriteStorage };
+use specs::{ Entities, Entity, Fetch, FetchMut, ReadStorage, System, WriteStorage };
 use winit::Window;
-use crate::{ Config, Map, OpalUi, Resources, RLock, WindowClosed };
+use crate::{ Config, Map, OpalUi, Resources, RLock, Time, WindowClosed };
 
 use back;
 use back::Backend as B;
@@ -31,6 +31,9 @@ pub mod conv;
 mod image;
 pub use self::image::{ Image, ImageKey, Sampler };
 
+mod interpolation;
+pub use self::interpolation::{ Easing, Interpolation, InterpolationSystem };
+
 mod light;
 pub use self::light::{ LightType, Light, LightData };
 
@@ -38,7 +41,7 @@ mod material;
 pub use self::material::{ MaterialDesc, Material, SurfaceType };
 
 pub mod model;
-pub use self::model::{ ModelKey, Model, ModelData, ModelType, ProceduralModel, Vertex, UiVertex };
+pub use self::model::{ ModelBounds, ModelKey, Model, ModelData, ModelType, ProceduralModel, Vertex, UiVertex };
 
 mod pipe;
 pub use self::pipe::{
@@ -219,18 +222,34 @@ impl<'a> Renderer<'a> {
     }
 }
 
+// prefers the interpolated position so that moving entities don't jump between cells,
+// anything else is drawn `alpha` of the way from where it was before the last tick
+pub(crate) fn world_position<'a>(map: &Map, interpolations: &ReadStorage<'a, Interpolation>, alpha: f32, entity: Entity) -> Vector3<f32> {
+    if let Some(position) = interpolations.get(entity).and_then(|interpolation| interpolation.position()) {
+        return position;
+    }
+
+    match (map.previous_location(&entity), map.location(&entity)) {
+        (Some(previous), Some(location)) => map.world_position(previous).lerp(map.world_position(location), alpha),
+        (None, Some(location)) => map.world_position(location),
+        (_, None) => Vector3::new(0.0, 0.0, 0.0),
+    }
+}
+
 impl<'a, 'b> System<'a> for Renderer<'b> {
     type SystemData = (
         Entities<'a>,
         WriteStorage<'a, ModelKey>, ReadStorage<'a, MaterialDesc>, ReadStorage<'a, ModelData>,
         ReadStorage<'a, Light>,
+        ReadStorage<'a, Interpolation>,
         Fetch<'a, Camera>,
         Fetch<'a, RLock<Map>>,
         FetchMut<'a, OpalUi>,
         Fetch<'a, WindowClosed>,
+        Fetch<'a, Time>,
     );
 
-    fn run(&mut self, (entities, mut model_keys, material_descs, model_datas, lights, camera, map, mut opal_ui, window_closed): Self::SystemData) {
+    fn run(&mut self, (entities, mut model_keys, material_descs, model_datas, lights, interpolations, camera, map, mut opal_ui, window_closed, time): Self::SystemData) {
         use specs::Join;
 
         if *window_closed == true {
@@ -326,11 +345,7 @@ impl<'a, 'b> System<'a> for Renderer<'b> {
                     None => Default::default(),
                 };
 
-                let position = match map.location(&entity) {
-                    Some(position) => *position,
-                    None => Vector3::new(0, 0, 0),
-                };
-
+                let position = world_position(&map, &interpolations, time.alpha(), entity);
                 let model_data = model_data.to_matrix(&position);
 
                 let mut normal_data = model_data.invert().unwrap();
@@ -351,10 +366,7 @@ impl<'a, 'b> System<'a> for Renderer<'b> {
             .map(|(entity, light)| {
                 let map = map.read().unwrap();
 
-                let position = match map.location(&entity) {
-                    Some(p) => Vector3::new(p.x as f32, p.y as f32, p.z as f32),
-                    None => Vector3::new(0.0, 0.0, 0.0),
-                };
+                let position = world_position(&map, &interpolations, time.alpha(), entity);
 
                 let mut model_data = match model_datas.get(entity) {
                     Some(data) => *data,
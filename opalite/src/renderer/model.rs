@@ -83,7 +83,7 @@ impl PartialEq for ModelKey {
 
 impl Eq for ModelKey { }
 
-#[derive(Component, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, Component, PartialEq, Clone, Copy)]
 pub struct ModelData {
     pub ignore_position: bool,
     pub translate: Vector3<f32>,
@@ -101,11 +101,11 @@ impl Default for ModelData {
 }
 
 impl ModelData {
-    pub fn to_matrix(&self, position: &Vector3<i32>) -> Matrix4<f32> {
+    pub fn to_matrix(&self, position: &Vector3<f32>) -> Matrix4<f32> {
         let position = if self.ignore_position {
             Vector3::new(0.0, 0.0, 0.0)
         } else {
-            Vector3::new(position.x as f32, position.y as f32, position.z as f32)
+            *position
         };
 
         let translate = self.translate + position;
@@ -116,11 +116,70 @@ impl ModelData {
     }
 }
 
+// the geometry of a model in model space, kept on the CPU for picking
+#[derive(Clone, Debug)]
+pub struct ModelBounds {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+    pub triangles: Vec<[Vector3<f32>; 3]>,
+}
+
+impl ModelBounds {
+    // `None` if there are no triangles, triangles with indices past the end of their mesh are skipped
+    pub fn from_meshes(meshes: &[(Vec<Vertex>, Vec<u32>)]) -> Option<Self> {
+        let triangles = meshes.iter()
+            .flat_map(|(vertices, indices)| {
+                let position = move |index: u32| vertices.get(index as usize).map(|vertex| vertex.position);
+
+                indices.chunks(3)
+                    .filter(|chunk| chunk.len() == 3)
+                    .filter_map(move |chunk| Some([
+                        position(chunk[0])?,
+                        position(chunk[1])?,
+                        position(chunk[2])?,
+                    ]))
+            })
+            .collect::<Vec<_>>();
+
+        let mut points = triangles.iter().flat_map(|triangle| triangle.iter());
+        let first = *points.next()?;
+
+        let (min, max) = points.fold((first, first), |(min, max), point| (
+            Vector3::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z)),
+            Vector3::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z)),
+        ));
+
+        Some(ModelBounds { min, max, triangles })
+    }
+
+    // built from the same geometry the renderer loads, `None` for procedural models without bounds
+    pub fn from_key(key: &ModelKey, resources: &RLock<Resources>) -> Result<Option<Self>, Error> {
+        let white = [1.0, 1.0, 1.0, 1.0];
+
+        let meshes = match key.ty() {
+            ModelType::Quad => vec![(make_quad(white).to_vec(), (0..6 as u32).collect())],
+            ModelType::Hex => {
+                let (vertices, indices) = make_hex(white);
+                vec![(vertices.to_vec(), indices.to_vec())]
+            },
+            ModelType::Sphere => vec![make_sphere(white)],
+            ModelType::File(path) => Model::meshes_from_file(path, resources)?,
+            ModelType::Procedural(procedural) => return Ok(procedural.lock().unwrap().bounds()),
+        };
+
+        Ok(ModelBounds::from_meshes(&meshes))
+    }
+}
+
 pub trait ProceduralModel {
     fn load(&mut self, device: Arc<Mutex<<B as Backend>::Device>>, memory_types: &[hal::MemoryType]) -> Vec<RLock<Model>>;
     fn needs_reload(&mut self) -> bool {
         false
     }
+    // asked for every pick, models without bounds are picked as a unit sphere
+    fn bounds(&mut self) -> Option<ModelBounds> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -167,7 +226,8 @@ impl Model {
         (vertices, indices)
     }
 
-    pub fn from_file(path: &PathBuf, resources: &RLock<Resources>, device: Arc<Mutex<<B as Backend>::Device>>, memory_types: &[hal::MemoryType]) -> Result<Vec<RLock<Self>>, Error> {
+    // the vertices and indices of each mesh, without uploading them
+    pub(crate) fn meshes_from_file(path: &PathBuf, resources: &RLock<Resources>) -> Result<Vec<(Vec<Vertex>, Vec<u32>)>, Error> {
         let resources = resources.read().unwrap();
         let gltf = resources.get(path)?;
         let (gltf, buffers) = gltf_importer::import_data_slice(&gltf[..], path, &Default::default())?;
@@ -211,6 +271,16 @@ impl Model {
                 }
             }
 
+            output_meshes.push((vertices, indices));
+        }
+
+        Ok(output_meshes)
+    }
+
+    pub fn from_file(path: &PathBuf, resources: &RLock<Resources>, device: Arc<Mutex<<B as Backend>::Device>>, memory_types: &[hal::MemoryType]) -> Result<Vec<RLock<Self>>, Error> {
+        let mut output_meshes = vec![];
+
+        for (vertices, indices) in Model::meshes_from_file(path, resources)? {
             let mut vertex_buffer = Buffer::<Vertex, B>::new(device.clone(), vertices.len() as u64, hal::buffer::Usage::VERTEX, &memory_types).unwrap();
             vertex_buffer.write(&vertices[..]).unwrap();
 
@@ -361,3 +431,36 @@ pub fn make_sphere<C: Into<Vector4<f32>>>(color: C) -> (Vec<Vertex>, Vec<u32>) {
 
     (vertices, indices)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            position: Vector3::new(x, y, z),
+            color: Vector4::new(1.0, 1.0, 1.0, 1.0),
+            uv: Vector2::new(0.0, 0.0),
+            normal: Vector3::new(0.0, 1.0, 0.0),
+        }
+    }
+
+    #[test]
+    fn bounds_skip_triangles_with_bad_indices() {
+        let vertices = vec![vertex(0.0, 0.0, 0.0), vertex(1.0, 0.0, 0.0), vertex(0.0, 0.0, 1.0)];
+        let meshes = vec![(vertices, vec![0, 1, 2, 0, 1, 7])];
+
+        let bounds = ModelBounds::from_meshes(&meshes).unwrap();
+
+        assert_eq!(bounds.triangles.len(), 1);
+        assert_eq!(bounds.min, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(bounds.max, Vector3::new(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn bounds_need_a_valid_triangle() {
+        let meshes = vec![(vec![vertex(0.0, 0.0, 0.0)], vec![0, 1, 2])];
+
+        assert!(ModelBounds::from_meshes(&meshes).is_none());
+    }
+}
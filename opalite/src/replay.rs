@@ -0,0 +1,355 @@
+use std::{ collections::BTreeMap, fs, path::PathBuf, sync::{ Arc, Mutex } };
+use bincode;
+use cgmath::Vector3;
+use failure::Error;
+use winit::{ ElementState, ModifiersState, MouseButton, MouseScrollDelta };
+use crate::{ virtual_key_from_name, InputEvent, MapMessage };
+
+// bumped whenever `ReplayFile` changes shape
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ReplayButton {
+    Left,
+    Right,
+    Middle,
+    Other(u8),
+}
+
+impl From<MouseButton> for ReplayButton {
+    fn from(button: MouseButton) -> Self {
+        match button {
+            MouseButton::Left => ReplayButton::Left,
+            MouseButton::Right => ReplayButton::Right,
+            MouseButton::Middle => ReplayButton::Middle,
+            MouseButton::Other(other) => ReplayButton::Other(other),
+        }
+    }
+}
+
+impl From<ReplayButton> for MouseButton {
+    fn from(button: ReplayButton) -> Self {
+        match button {
+            ReplayButton::Left => MouseButton::Left,
+            ReplayButton::Right => MouseButton::Right,
+            ReplayButton::Middle => MouseButton::Middle,
+            ReplayButton::Other(other) => MouseButton::Other(other),
+        }
+    }
+}
+
+fn pressed(state: ElementState) -> bool {
+    state == ElementState::Pressed
+}
+
+fn element_state(pressed: bool) -> ElementState {
+    if pressed { ElementState::Pressed } else { ElementState::Released }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq)]
+pub struct ReplayModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+impl From<ModifiersState> for ReplayModifiers {
+    fn from(modifiers: ModifiersState) -> Self {
+        let ModifiersState { shift, ctrl, alt, logo } = modifiers;
+        ReplayModifiers { shift, ctrl, alt, logo }
+    }
+}
+
+impl From<ReplayModifiers> for ModifiersState {
+    fn from(modifiers: ReplayModifiers) -> Self {
+        let ReplayModifiers { shift, ctrl, alt, logo } = modifiers;
+        ModifiersState { shift, ctrl, alt, logo }
+    }
+}
+
+// `InputEvent` can't be serialized because of the winit types in it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ReplayInput {
+    MouseClicked { pressed: bool, button: ReplayButton },
+    MouseClickedWithCoordinates { pressed: bool, button: ReplayButton, x: f64, y: f64 },
+    MouseCoordinates { x: f64, y: f64 },
+    // `lines` is false for pixel deltas
+    MouseScrolled { x: f32, y: f32, lines: bool },
+    // the virtual key is stored by name
+    Keyboard { pressed: bool, scancode: u32, key: Option<String>, modifiers: ReplayModifiers },
+    Text { character: char },
+    // losing focus releases every action, and the picker needs the size for mouse coordinates
+    Focused { focused: bool },
+    Resized { width: u32, height: u32 },
+}
+
+impl From<InputEvent> for ReplayInput {
+    fn from(event: InputEvent) -> Self {
+        match event {
+            InputEvent::MouseClicked { state, button } => ReplayInput::MouseClicked {
+                pressed: pressed(state),
+                button: button.into(),
+            },
+            InputEvent::MouseClickedWithCoordinates { state, button, x, y } => ReplayInput::MouseClickedWithCoordinates {
+                pressed: pressed(state),
+                button: button.into(),
+                x, y,
+            },
+            InputEvent::MouseCoordinates { x, y } => ReplayInput::MouseCoordinates { x, y },
+            InputEvent::MouseScrolled { delta } => match delta {
+                MouseScrollDelta::LineDelta(x, y) => ReplayInput::MouseScrolled { x, y, lines: true },
+                MouseScrollDelta::PixelDelta(x, y) => ReplayInput::MouseScrolled { x, y, lines: false },
+            },
+            InputEvent::Keyboard { state, scancode, virtual_keycode, modifiers } => ReplayInput::Keyboard {
+                pressed: pressed(state),
+                scancode,
+                key: virtual_keycode.map(|key| format!("{:?}", key)),
+                modifiers: modifiers.into(),
+            },
+            InputEvent::Text { character } => ReplayInput::Text { character },
+            InputEvent::Focused { focused } => ReplayInput::Focused { focused },
+            InputEvent::Resized { width, height } => ReplayInput::Resized { width, height },
+        }
+    }
+}
+
+impl From<ReplayInput> for InputEvent {
+    fn from(input: ReplayInput) -> Self {
+        match input {
+            ReplayInput::MouseClicked { pressed, button } => InputEvent::MouseClicked {
+                state: element_state(pressed),
+                button: button.into(),
+            },
+            ReplayInput::MouseClickedWithCoordinates { pressed, button, x, y } => InputEvent::MouseClickedWithCoordinates {
+                state: element_state(pressed),
+                button: button.into(),
+                x, y,
+            },
+            ReplayInput::MouseCoordinates { x, y } => InputEvent::MouseCoordinates { x, y },
+            ReplayInput::MouseScrolled { x, y, lines } => InputEvent::MouseScrolled {
+                delta: if lines { MouseScrollDelta::LineDelta(x, y) } else { MouseScrollDelta::PixelDelta(x, y) },
+            },
+            ReplayInput::Keyboard { pressed, scancode, key, modifiers } => InputEvent::Keyboard {
+                state: element_state(pressed),
+                scancode,
+                virtual_keycode: key.and_then(|key| virtual_key_from_name(&key)),
+                modifiers: modifiers.into(),
+            },
+            ReplayInput::Text { character } => InputEvent::Text { character },
+            ReplayInput::Focused { focused } => InputEvent::Focused { focused },
+            ReplayInput::Resized { width, height } => InputEvent::Resized { width, height },
+        }
+    }
+}
+
+// entities are stored by id and generation since they can't be serialized
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
+pub enum ReplayMapMessage {
+    Move { entity: (u32, i32), new_location: Vector3<i32>, absolute: bool },
+    Place { entity: (u32, i32), location: Vector3<i32> },
+    Reset { dimensions: Vector3<i32> },
+}
+
+impl<'a> From<&'a MapMessage> for ReplayMapMessage {
+    fn from(message: &'a MapMessage) -> Self {
+        match message {
+            MapMessage::Move { entity, new_location, absolute, .. } => ReplayMapMessage::Move {
+                entity: (entity.id(), entity.gen().id()),
+                new_location: *new_location,
+                absolute: *absolute,
+            },
+            MapMessage::Place { entity, location } => ReplayMapMessage::Place {
+                entity: (entity.id(), entity.gen().id()),
+                location: *location,
+            },
+            MapMessage::Reset { dimensions, .. } => ReplayMapMessage::Reset {
+                dimensions: *dimensions,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReplayTick {
+    pub inputs: Vec<ReplayInput>,
+    // only used to find where a desync started, they aren't played back
+    pub map_messages: Vec<ReplayMapMessage>,
+    pub checksum: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReplayFile {
+    pub version: u32,
+    // keyed by the number of ticks since recording started
+    pub ticks: BTreeMap<u64, ReplayTick>,
+}
+
+impl ReplayFile {
+    pub fn new() -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            ticks: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &PathBuf) -> Result<Self, Error> {
+        let bytes = fs::read(path)?;
+        let file: ReplayFile = bincode::deserialize(&bytes)?;
+
+        if file.version != REPLAY_VERSION {
+            bail!("Replay version {} isn't supported, expected {}.", file.version, REPLAY_VERSION);
+        }
+
+        Ok(file)
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), Error> {
+        let bytes = bincode::serialize(self)?;
+        fs::write(path, bytes)?;
+        Ok(())
+    }
+
+    pub fn last_tick(&self) -> u64 {
+        self.ticks.keys().next_back().cloned().unwrap_or(0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum ReplayMode {
+    Off,
+    Recording,
+    Playing,
+}
+
+#[derive(Debug)]
+struct Replay {
+    mode: ReplayMode,
+    // the `Time::tick` that recording or playback started at
+    start: u64,
+    // the tick that is about to run, or is running, counted from `start`
+    tick: u64,
+    file: ReplayFile,
+    desyncs: Vec<u64>,
+}
+
+// shared by `Opal`, `InputEventHandler` and `MapSystem`
+#[derive(Clone, Debug)]
+pub struct ReplayHandle(Arc<Mutex<Replay>>);
+
+impl ReplayHandle {
+    pub fn new() -> Self {
+        ReplayHandle(Arc::new(Mutex::new(Replay {
+            mode: ReplayMode::Off,
+            start: 0,
+            tick: 0,
+            file: ReplayFile::new(),
+            desyncs: vec![],
+        })))
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.0.lock().unwrap().mode == ReplayMode::Recording
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.0.lock().unwrap().mode == ReplayMode::Playing
+    }
+
+    // throws away anything that was recorded before, `start` is the first tick that's recorded
+    pub fn record(&self, start: u64) {
+        let mut replay = self.0.lock().unwrap();
+        replay.mode = ReplayMode::Recording;
+        replay.start = start;
+        replay.tick = 0;
+        replay.file = ReplayFile::new();
+        replay.desyncs.clear();
+    }
+
+    // the first recorded tick is played back at `start`
+    pub fn play(&self, file: ReplayFile, start: u64) {
+        let mut replay = self.0.lock().unwrap();
+        replay.mode = ReplayMode::Playing;
+        replay.start = start;
+        replay.tick = 0;
+        replay.file = file;
+        replay.desyncs.clear();
+    }
+
+    // returns what was recorded or played
+    pub fn stop(&self) -> ReplayFile {
+        let mut replay = self.0.lock().unwrap();
+        replay.mode = ReplayMode::Off;
+        replay.file.clone()
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), Error> {
+        self.0.lock().unwrap().file.save(path)
+    }
+
+    // the ticks where the map didn't match the recording, counted from the start of the replay
+    pub fn desyncs(&self) -> Vec<u64> {
+        self.0.lock().unwrap().desyncs.clone()
+    }
+
+    // `tick` is a `Time::tick`
+    pub(crate) fn begin_tick(&self, tick: u64) {
+        let mut replay = self.0.lock().unwrap();
+        replay.tick = tick.saturating_sub(replay.start);
+
+        if replay.mode == ReplayMode::Playing && replay.tick > replay.file.last_tick() {
+            replay.mode = ReplayMode::Off;
+        }
+    }
+
+    // inputs recorded for the current tick
+    pub(crate) fn inputs(&self) -> Vec<InputEvent> {
+        let replay = self.0.lock().unwrap();
+
+        if replay.mode != ReplayMode::Playing {
+            return vec![];
+        }
+
+        replay.file.ticks.get(&replay.tick)
+            .map(|tick| tick.inputs.iter().map(|input| input.clone().into()).collect())
+            .unwrap_or_else(|| vec![])
+    }
+
+    pub(crate) fn record_input(&self, event: InputEvent) {
+        let mut replay = self.0.lock().unwrap();
+
+        if replay.mode == ReplayMode::Recording {
+            let tick = replay.tick;
+            replay.file.ticks.entry(tick).or_insert_with(ReplayTick::default).inputs.push(event.into());
+        }
+    }
+
+    pub(crate) fn record_map_message(&self, message: &MapMessage) {
+        let mut replay = self.0.lock().unwrap();
+
+        if replay.mode == ReplayMode::Recording {
+            let tick = replay.tick;
+            replay.file.ticks.entry(tick).or_insert_with(ReplayTick::default).map_messages.push(message.into());
+        }
+    }
+
+    // records the checksum, or compares it against the recording
+    pub(crate) fn check(&self, checksum: u64) {
+        let mut replay = self.0.lock().unwrap();
+        let tick = replay.tick;
+
+        match replay.mode {
+            ReplayMode::Recording => {
+                replay.file.ticks.entry(tick).or_insert_with(ReplayTick::default).checksum = Some(checksum);
+            },
+            ReplayMode::Playing => {
+                let expected = replay.file.ticks.get(&tick).and_then(|tick| tick.checksum);
+
+                if expected.is_some() && expected != Some(checksum) {
+                    replay.desyncs.push(tick);
+                }
+            },
+            ReplayMode::Off => (),
+        }
+    }
+}
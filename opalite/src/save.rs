@@ -0,0 +1,370 @@
+use std::{ any::TypeId, collections::BTreeMap, fs, path::PathBuf, sync::Arc };
+use cgmath::Vector3;
+use failure::Error;
+use ron;
+use serde::{ de::DeserializeOwned, Serialize };
+use specs::{ Component, Entity, Join, World };
+use crate::{
+    AiComponent,
+    AiScript,
+    Behaviour,
+    BehaviourTree,
+    CollisionLayers,
+    Data,
+    Easing,
+    InitialPosition,
+    Initiative,
+    Interpolation,
+    Light,
+    Map,
+    MapMessage,
+    MaterialDesc,
+    MessageSender,
+    ModelData,
+    ModelKey,
+    ModelType,
+    Placement,
+    Position,
+    RLock,
+    SightBlockers,
+    Viewshed,
+};
+use crate::gluon_api::{ DataReference, GluonUiComponent };
+
+// bumped whenever `SaveFile` changes shape
+pub const SAVE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SavedEntity {
+    pub location: Option<Vector3<i32>>,
+    // each value is the component or data serialized on its own, keyed by the name it was registered with
+    pub components: BTreeMap<String, String>,
+    pub data: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SaveFile {
+    pub version: u32,
+    pub entities: Vec<SavedEntity>,
+}
+
+impl SaveFile {
+    pub fn load(path: &PathBuf) -> Result<Self, Error> {
+        let file = fs::read_to_string(path)?;
+        let file: SaveFile = ron::de::from_str(&file)?;
+
+        if file.version != SAVE_VERSION {
+            bail!("Save version {} isn't supported, expected {}.", file.version, SAVE_VERSION);
+        }
+
+        Ok(file)
+    }
+
+    pub fn save(&self, path: &PathBuf) -> Result<(), Error> {
+        let file = ron::ser::to_string(self)?;
+        fs::write(path, file)?;
+        Ok(())
+    }
+}
+
+// `ModelKey` can't be saved directly because of `ModelType::Procedural`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SavedModel {
+    Quad,
+    Hex,
+    Sphere,
+    File(PathBuf),
+}
+
+impl SavedModel {
+    // `None` for procedural models, which have to be recreated by the game
+    pub fn from_key(key: &ModelKey) -> Option<Self> {
+        match key.ty() {
+            ModelType::Quad => Some(SavedModel::Quad),
+            ModelType::Hex => Some(SavedModel::Hex),
+            ModelType::Sphere => Some(SavedModel::Sphere),
+            ModelType::File(path) => Some(SavedModel::File(path.clone())),
+            ModelType::Procedural(_) => None,
+        }
+    }
+
+    pub fn to_key(self) -> ModelKey {
+        ModelKey::new(match self {
+            SavedModel::Quad => ModelType::Quad,
+            SavedModel::Hex => ModelType::Hex,
+            SavedModel::Sphere => ModelType::Sphere,
+            SavedModel::File(path) => ModelType::File(path),
+        })
+    }
+}
+
+// a saved value that has been parsed and is ready to be put on an entity
+pub(crate) type ParsedValue = Box<FnMut(&World, Entity)>;
+
+struct SaveEntry {
+    name: String,
+    ty: TypeId,
+    has: Box<Fn(&World, Entity) -> bool + Send + Sync>,
+    save: Box<Fn(&World, Entity) -> Result<Option<String>, Error> + Send + Sync>,
+    parse: Box<Fn(&str) -> Result<ParsedValue, Error> + Send + Sync>,
+}
+
+struct RefusedEntry {
+    name: String,
+    ty: TypeId,
+    refuses: Box<Fn(&World, Entity) -> bool + Send + Sync>,
+}
+
+// decides which components and `Data` entries end up in a save file
+#[derive(Clone)]
+pub struct SaveRegistry {
+    components: Vec<Arc<SaveEntry>>,
+    data: Vec<Arc<SaveEntry>>,
+    refused: Vec<Arc<RefusedEntry>>,
+}
+
+impl SaveRegistry {
+    pub fn new() -> Self {
+        Self {
+            components: vec![],
+            data: vec![],
+            refused: vec![],
+        }
+    }
+
+    // everything opalite owns that can be saved
+    pub fn with_defaults() -> Self {
+        let mut registry = SaveRegistry::new();
+
+        registry.register_component::<InitialPosition>("InitialPosition");
+        registry.register_component::<CollisionLayers>("CollisionLayers");
+        registry.register_component::<MaterialDesc>("MaterialDesc");
+        registry.register_component::<ModelData>("ModelData");
+        registry.register_component::<Light>("Light");
+        registry.register_component::<Initiative>("Initiative");
+        registry.register_component::<Placement>("Placement");
+        registry.register_component::<AiScript>("AiScript");
+        registry.register_component_with::<ModelKey, SavedModel>("ModelKey", SavedModel::from_key, SavedModel::to_key);
+        // only the settings are saved, the rest is worked out again after loading
+        registry.register_component_with::<Interpolation, (f32, Easing)>("Interpolation",
+            |interpolation| Some((interpolation.duration, interpolation.easing)),
+            |(duration, easing)| Interpolation::new(duration, easing));
+        registry.register_component_with::<Viewshed, (i32, SightBlockers)>("Viewshed",
+            |viewshed| Some((viewshed.radius(), viewshed.blockers().clone())),
+            |(radius, blockers)| Viewshed::new(radius, blockers));
+        // behaviour trees start again from the root
+        registry.register_component_with::<AiComponent, Behaviour>("AiComponent",
+            |ai| ai.behaviour().map(|behaviour| behaviour.root().clone()),
+            |root| AiComponent::from_behaviour(BehaviourTree::new(root)));
+
+        // closures and entities can't be saved, a game that wants them has to register its own conversion
+        registry.refuse_component::<AiComponent>("AiComponent", |ai| ai.behaviour().is_none());
+        registry.refuse_component::<GluonUiComponent>("GluonUiComponent", |_| true);
+        registry.refuse_component::<DataReference>("DataReference", |_| true);
+
+        registry
+    }
+
+    pub fn register_component<C>(&mut self, name: &str) where C: Component + Clone + Serialize + DeserializeOwned + Send + Sync {
+        self.register_component_with::<C, C>(name, |component| Some(component.clone()), |component| component);
+    }
+
+    // for components that have to be converted into something else to be saved,
+    // `to_saved` returning `None` leaves the component out. Replaces anything already registered
+    // or refused for `C`
+    pub fn register_component_with<C, S>(&mut self, name: &str, to_saved: fn(&C) -> Option<S>, from_saved: fn(S) -> C)
+        where C: Component + Send + Sync, S: Serialize + DeserializeOwned + 'static
+    {
+        let ty = TypeId::of::<C>();
+        self.components.retain(|entry| entry.ty != ty);
+        self.refused.retain(|entry| entry.ty != ty);
+
+        self.components.push(Arc::new(SaveEntry {
+            name: name.to_owned(),
+            ty,
+            has: Box::new(|world, entity| world.read::<C>().get(entity).is_some()),
+            save: Box::new(move |world, entity| {
+                let storage = world.read::<C>();
+                match storage.get(entity).and_then(to_saved) {
+                    Some(saved) => Ok(Some(ron::ser::to_string(&saved)?)),
+                    None => Ok(None),
+                }
+            }),
+            parse: Box::new(move |value| {
+                let mut saved = Some(ron::de::from_str::<S>(value)?);
+                Ok(Box::new(move |world: &World, entity| {
+                    if let Some(saved) = saved.take() {
+                        world.write::<C>().insert(entity, from_saved(saved));
+                    }
+                }))
+            }),
+        }));
+    }
+
+    // `save` fails for entities with a `C` that `refuses` returns true for, rather than losing it
+    pub fn refuse_component<C>(&mut self, name: &str, refuses: fn(&C) -> bool) where C: Component + Send + Sync {
+        self.refused.push(Arc::new(RefusedEntry {
+            name: name.to_owned(),
+            ty: TypeId::of::<C>(),
+            refuses: Box::new(move |world, entity| world.read::<C>().get(entity).map(refuses).unwrap_or(false)),
+        }));
+    }
+
+    // `Data` entries of type `T` are only saved once they're registered
+    pub fn register_data<T>(&mut self, name: &str) where T: Clone + Serialize + DeserializeOwned + Send + Sync + 'static {
+        let ty = TypeId::of::<T>();
+        self.data.retain(|entry| entry.ty != ty);
+
+        self.data.push(Arc::new(SaveEntry {
+            name: name.to_owned(),
+            ty,
+            has: Box::new(|world, entity| {
+                world.read::<Data>().get(entity)
+                    .map(|data| data.contains::<T>())
+                    .unwrap_or(false)
+            }),
+            save: Box::new(|world, entity| {
+                let datas = world.read::<Data>();
+                match datas.get(entity).and_then(|data| data.get::<T>()) {
+                    Some(value) => Ok(Some(ron::ser::to_string(&value)?)),
+                    None => Ok(None),
+                }
+            }),
+            parse: Box::new(|value| {
+                let mut value = Some(ron::de::from_str::<T>(value)?);
+                Ok(Box::new(move |world: &World, entity| {
+                    if let Some(value) = value.take() {
+                        let mut datas = world.write::<Data>();
+
+                        if datas.get(entity).is_none() {
+                            datas.insert(entity, Data::new());
+                        }
+                        datas.get(entity).unwrap().insert(value);
+                    }
+                }))
+            }),
+        }));
+    }
+
+    fn parse_component(&self, name: &str, value: &str) -> Result<ParsedValue, Error> {
+        match self.components.iter().find(|entry| entry.name == name) {
+            Some(entry) => (entry.parse)(value),
+            None => bail!("Component '{}' isn't registered with the SaveRegistry.", name),
+        }
+    }
+
+    // a single `Data` entry registered as `name`, used by prefabs for their initial values
+    pub(crate) fn parse_data(&self, name: &str, value: &str) -> Result<ParsedValue, Error> {
+        match self.data.iter().find(|entry| entry.name == name) {
+            Some(entry) => (entry.parse)(value),
+            None => bail!("Data type '{}' isn't registered with the SaveRegistry.", name),
+        }
+    }
+
+    // entities on the map or with anything registered
+    pub fn is_saved(&self, world: &World, entity: Entity) -> bool {
+        let map = world.read_resource::<RLock<Map>>();
+        let on_map = map.read().unwrap().location(&entity).is_some();
+
+        on_map || self.components.iter().chain(self.data.iter()).any(|entry| (entry.has)(world, entity))
+    }
+
+    pub fn save(&self, world: &World) -> Result<SaveFile, Error> {
+        let entities = world.entities().join()
+            .filter(|entity| self.is_saved(world, *entity))
+            .collect::<Vec<_>>();
+
+        let mut saved = vec![];
+
+        for entity in entities {
+            if let Some(refused) = self.refused.iter().find(|entry| (entry.refuses)(world, entity)) {
+                bail!("{:?} has a {}, which can't be saved.", entity, refused.name);
+            }
+
+            let location = {
+                let map = world.read_resource::<RLock<Map>>();
+                let map = map.read().unwrap();
+                map.location(&entity).cloned()
+            };
+
+            let mut saved_entity = SavedEntity {
+                location,
+                .. Default::default()
+            };
+
+            for entry in &self.components {
+                if let Some(value) = (entry.save)(world, entity)? {
+                    saved_entity.components.insert(entry.name.clone(), value);
+                }
+            }
+
+            for entry in &self.data {
+                if let Some(value) = (entry.save)(world, entity)? {
+                    saved_entity.data.insert(entry.name.clone(), value);
+                }
+            }
+
+            saved.push(saved_entity);
+        }
+
+        Ok(SaveFile {
+            version: SAVE_VERSION,
+            entities: saved,
+        })
+    }
+
+    // deletes everything that `save` would have saved and creates the entities in `file`,
+    // they're put on the map the next time `MapSystem` runs. Every value is parsed first,
+    // so the world is left as it was if any of them are invalid
+    pub fn load(&self, world: &mut World, file: SaveFile) -> Result<Vec<Entity>, Error> {
+        let mut parsed = vec![];
+
+        for saved in file.entities {
+            let mut values = vec![];
+
+            for (name, value) in &saved.components {
+                values.push(self.parse_component(name, value)?);
+            }
+
+            for (name, value) in &saved.data {
+                values.push(self.parse_data(name, value)?);
+            }
+
+            parsed.push((saved.location, values));
+        }
+
+        let old = world.entities().join()
+            .filter(|entity| self.is_saved(world, *entity))
+            .collect::<Vec<_>>();
+
+        world.delete_entities(&old[..])
+            .map_err(|err| format_err!("{:?}", err))?;
+        world.maintain();
+
+        let mut created = vec![];
+
+        for (location, values) in parsed {
+            let entity = world.create_entity().build();
+
+            for mut value in values {
+                value(world, entity);
+            }
+
+            if let Some(location) = location {
+                // `Position` stops `MapSystem` from placing the entity at its `InitialPosition` first
+                world.write::<Position>().insert(entity, Position);
+                world.write_resource::<MessageSender<MapMessage>>().send(MapMessage::Place { entity, location });
+            }
+
+            created.push(entity);
+        }
+
+        Ok(created)
+    }
+}
+
+impl Default for SaveRegistry {
+    fn default() -> Self {
+        SaveRegistry::with_defaults()
+    }
+}
@@ -1,4 +1,8 @@
-use std::sync::{ Arc, Mutex, MutexGuard, mpsc };
+use std::{
+    collections::{ HashMap, VecDeque },
+    sync::{ atomic::{ AtomicUsize, Ordering }, Arc, Mutex, MutexGuard, mpsc },
+};
+use anymap::{ any, Map as AnyMap };
 use owning_ref::MutexGuardRefMut;
 use specs::System;
 
@@ -63,8 +67,237 @@ impl<M: Message> MessageReceiver<M> {
     }
 }
 
+// how many frames an event is kept for subscribers that haven't read it yet
+pub const EVENT_LIFETIME: u64 = 60;
+
+struct EventChannel<M> {
+    // each event with the frame it was published in
+    events: VecDeque<(u64, M)>,
+    // the id of the first event in `events`, ids count up from 0
+    first: u64,
+    // the id of the next event each reader will read
+    cursors: HashMap<usize, u64>,
+    next_reader: usize,
+    frame: Arc<AtomicUsize>,
+    lifetime: u64,
+}
+
+impl<M> EventChannel<M> {
+    fn new(frame: Arc<AtomicUsize>, lifetime: u64) -> Self {
+        Self {
+            events: VecDeque::new(),
+            first: 0,
+            cursors: HashMap::new(),
+            next_reader: 0,
+            frame,
+            lifetime,
+        }
+    }
+
+    fn end(&self) -> u64 {
+        self.first + self.events.len() as u64
+    }
+
+    // drops events every reader has read, or that are too old
+    fn clean(&mut self) {
+        let frame = self.frame.load(Ordering::SeqCst) as u64;
+        let read_by_all = self.cursors.values().cloned().min().unwrap_or(self.end());
+
+        while let Some(published) = self.events.front().map(|(published, _)| *published) {
+            if self.first >= read_by_all && frame < published + self.lifetime {
+                break;
+            }
+
+            self.events.pop_front();
+            self.first += 1;
+        }
+    }
+}
+
+// a subscription to the events of type `M` on an `EventBus`, dropping it unsubscribes
+pub struct EventReader<M: Message> {
+    channel: Arc<Mutex<EventChannel<M>>>,
+    id: usize,
+}
+
+impl<M: Message + Clone> EventReader<M> {
+    // every event published since the last read that hasn't expired
+    pub fn read(&mut self) -> Vec<M> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.clean();
+
+        let end = channel.end();
+        let cursor = channel.cursors.get(&self.id).cloned().unwrap_or(end).max(channel.first);
+        let events = channel.events.iter()
+            .skip((cursor - channel.first) as usize)
+            .map(|(_, event)| event.clone())
+            .collect();
+
+        channel.cursors.insert(self.id, end);
+        channel.clean();
+
+        events
+    }
+}
+
+impl<M: Message> Drop for EventReader<M> {
+    fn drop(&mut self) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.cursors.remove(&self.id);
+    }
+}
+
+// any number of subscribers per message type, each with its own read cursor
+#[derive(Clone)]
+pub struct EventBus {
+    channels: Arc<Mutex<AnyMap<any::Any + Send + Sync>>>,
+    frame: Arc<AtomicUsize>,
+    lifetime: u64,
+}
+
+impl EventBus {
+    // events are dropped `lifetime` frames after they're published, even if they haven't been read
+    pub fn new(lifetime: u64) -> Self {
+        EventBus {
+            channels: Arc::new(Mutex::new(AnyMap::new())),
+            frame: Arc::new(AtomicUsize::new(0)),
+            lifetime,
+        }
+    }
+
+    fn channel<M: Message + Send + 'static>(&self) -> Arc<Mutex<EventChannel<M>>> {
+        let mut channels = self.channels.lock().unwrap();
+        let frame = self.frame.clone();
+        let lifetime = self.lifetime;
+
+        channels.entry::<Arc<Mutex<EventChannel<M>>>>()
+            .or_insert_with(|| Arc::new(Mutex::new(EventChannel::new(frame, lifetime))))
+            .clone()
+    }
+
+    // the reader only sees events published after it subscribed
+    pub fn subscribe<M: Message + Send + 'static>(&self) -> EventReader<M> {
+        let channel = self.channel::<M>();
+
+        let id = {
+            let mut channel = channel.lock().unwrap();
+            let id = channel.next_reader;
+            let end = channel.end();
+
+            channel.next_reader += 1;
+            channel.cursors.insert(id, end);
+            id
+        };
+
+        EventReader { channel, id }
+    }
+
+    // events without subscribers are dropped right away
+    pub fn publish<M: Message + Send + 'static>(&self, message: M) {
+        let channel = self.channel::<M>();
+        let mut channel = channel.lock().unwrap();
+        let frame = self.frame.load(Ordering::SeqCst) as u64;
+
+        channel.events.push_back((frame, message));
+        channel.clean();
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame.load(Ordering::SeqCst) as u64
+    }
+
+    pub(crate) fn advance(&self) {
+        self.frame.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        EventBus::new(EVENT_LIFETIME)
+    }
+}
+
 pub trait Shard<'a>: System<'a> {
     type Message: Message;
 
     fn sender(&self) -> MessageSender<Self::Message>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    struct TestEvent(u32);
+
+    impl Message for TestEvent { }
+
+    fn stored(bus: &EventBus) -> usize {
+        bus.channel::<TestEvent>().lock().unwrap().events.len()
+    }
+
+    #[test]
+    fn readers_read_at_their_own_pace() {
+        let bus = EventBus::new(EVENT_LIFETIME);
+        let mut fast = bus.subscribe::<TestEvent>();
+        let mut slow = bus.subscribe::<TestEvent>();
+
+        bus.publish(TestEvent(1));
+        assert_eq!(fast.read(), vec![TestEvent(1)]);
+
+        bus.publish(TestEvent(2));
+        assert_eq!(fast.read(), vec![TestEvent(2)]);
+        assert_eq!(fast.read(), vec![]);
+
+        assert_eq!(slow.read(), vec![TestEvent(1), TestEvent(2)]);
+        assert_eq!(stored(&bus), 0);
+    }
+
+    #[test]
+    fn events_expire_after_lifetime() {
+        let bus = EventBus::new(2);
+        let mut reader = bus.subscribe::<TestEvent>();
+
+        bus.publish(TestEvent(1));
+        bus.advance();
+        bus.publish(TestEvent(2));
+        bus.advance();
+
+        assert_eq!(reader.read(), vec![TestEvent(2)]);
+
+        bus.publish(TestEvent(3));
+        bus.advance();
+        bus.advance();
+
+        assert_eq!(reader.read(), vec![]);
+    }
+
+    #[test]
+    fn late_subscribers_miss_earlier_events() {
+        let bus = EventBus::new(EVENT_LIFETIME);
+        let _early = bus.subscribe::<TestEvent>();
+
+        bus.publish(TestEvent(1));
+        let mut late = bus.subscribe::<TestEvent>();
+        bus.publish(TestEvent(2));
+
+        assert_eq!(late.read(), vec![TestEvent(2)]);
+    }
+
+    #[test]
+    fn dropping_a_reader_releases_its_events() {
+        let bus = EventBus::new(EVENT_LIFETIME);
+        let mut reader = bus.subscribe::<TestEvent>();
+        let unread = bus.subscribe::<TestEvent>();
+
+        bus.publish(TestEvent(1));
+        bus.publish(TestEvent(2));
+        reader.read();
+        assert_eq!(stored(&bus), 2);
+
+        drop(unread);
+        bus.publish(TestEvent(3));
+        assert_eq!(reader.read(), vec![TestEvent(3)]);
+        assert_eq!(stored(&bus), 0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+use failure::Error;
+
+// a frame can't add more than this to the accumulator, so that a long stall doesn't
+// make the simulation try to catch up all at once
+const MAX_FRAME_TIME: f32 = 0.25;
+
+#[derive(Clone, Debug)]
+pub struct Time {
+    delta: f32,
+    total: f64,
+    frame: u64,
+    tick: u64,
+    fixed_delta: f32,
+    accumulator: f32,
+}
+
+impl Time {
+    // `tick_rate` is in ticks per second
+    pub fn new(tick_rate: f32) -> Result<Self, Error> {
+        if !(tick_rate > 0.0) {
+            bail!("Tick rate {} isn't above 0.", tick_rate);
+        }
+
+        Ok(Self {
+            delta: 0.0,
+            total: 0.0,
+            frame: 0,
+            tick: 0,
+            fixed_delta: 1.0 / tick_rate,
+            accumulator: 0.0,
+        })
+    }
+
+    // seconds since the last frame
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    // seconds since the first frame
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    // the number of frames that have been rendered
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // the number of simulation ticks that have run
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    // seconds per simulation tick
+    pub fn fixed_delta(&self) -> f32 {
+        self.fixed_delta
+    }
+
+    // how far the current frame is between the last tick and the next one, from 0 to 1
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / self.fixed_delta
+    }
+
+    // seconds of simulation, including the part of the next tick that has already passed
+    pub fn tick_time(&self) -> f64 {
+        (self.tick as f64 + self.alpha() as f64) * self.fixed_delta as f64
+    }
+
+    pub(crate) fn advance_frame(&mut self, elapsed: Duration) {
+        let delta = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0;
+
+        self.delta = delta;
+        self.total += delta as f64;
+        self.frame += 1;
+        self.accumulator += delta.min(MAX_FRAME_TIME);
+    }
+
+    // a frame with exactly one tick in it
+    pub(crate) fn advance_fixed(&mut self) {
+        self.delta = self.fixed_delta;
+        self.total += self.fixed_delta as f64;
+        self.frame += 1;
+        self.tick += 1;
+        self.accumulator = 0.0;
+    }
+
+    // true if there's enough time left over for another tick
+    pub(crate) fn advance_tick(&mut self) -> bool {
+        if self.accumulator < self.fixed_delta {
+            return false;
+        }
+
+        self.accumulator -= self.fixed_delta;
+        self.tick += 1;
+        true
+    }
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Time::new(30.0).unwrap()
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use specs::{ Entities, Entity, FetchMut, ReadStorage, System };
+use crate::{ AiComponent, Position };
+
+// how much energy an entity spends to take a turn
+pub const TURN_COST: u32 = 100;
+
+// entities with `Initiative` take turns when the scheduler is enabled,
+// an entity with a `speed` of `2 * x` gets twice as many turns as one with `x`
+#[derive(Component, Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Initiative {
+    pub speed: u32,
+    // the scheduler waits for `TurnScheduler::end_turn` or a move instead of letting `AiSystem` act
+    pub waits_for_input: bool,
+}
+
+impl Initiative {
+    pub fn new(speed: u32) -> Self {
+        Self {
+            speed,
+            waits_for_input: false,
+        }
+    }
+
+    pub fn player(speed: u32) -> Self {
+        Self {
+            speed,
+            waits_for_input: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Actor {
+    energy: u32,
+    speed: u32,
+    waits_for_input: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct TurnScheduler {
+    enabled: bool,
+    actors: HashMap<Entity, Actor>,
+    current: Option<Entity>,
+    turn_ended: bool,
+    turn: u64,
+}
+
+impl TurnScheduler {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            actors: HashMap::new(),
+            current: None,
+            turn_ended: false,
+            turn: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.current = None;
+        self.turn_ended = false;
+    }
+
+    pub fn current(&self) -> Option<Entity> {
+        self.current
+    }
+
+    // the number of turns that have started
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    // entities without `Initiative` aren't scheduled
+    pub fn is_scheduled(&self, entity: Entity) -> bool {
+        self.enabled && self.actors.contains_key(&entity)
+    }
+
+    // always true when the scheduler is disabled or the entity isn't scheduled
+    pub fn is_turn(&self, entity: Entity) -> bool {
+        !self.is_scheduled(entity) || (self.current == Some(entity) && !self.turn_ended)
+    }
+
+    pub fn waiting_for_input(&self) -> bool {
+        self.enabled && !self.turn_ended && self.current
+            .and_then(|current| self.actors.get(&current))
+            .map(|actor| actor.waits_for_input)
+            .unwrap_or(false)
+    }
+
+    // does nothing if it isn't `entity`'s turn
+    pub fn end_turn(&mut self, entity: Entity) {
+        if self.enabled && self.current == Some(entity) {
+            self.turn_ended = true;
+        }
+    }
+
+    fn next_turn(&mut self) {
+        if self.actors.values().all(|actor| actor.speed == 0) {
+            self.current = None;
+            return;
+        }
+
+        loop {
+            // the entity with the most energy goes first, ties go to the oldest entity
+            let ready = self.actors.iter()
+                .filter(|(_, actor)| actor.energy >= TURN_COST)
+                .max_by(|(a, a_actor), (b, b_actor)| a_actor.energy.cmp(&b_actor.energy).then(b.id().cmp(&a.id())))
+                .map(|(entity, _)| *entity);
+
+            if let Some(entity) = ready {
+                self.actors.get_mut(&entity).unwrap().energy -= TURN_COST;
+                self.current = Some(entity);
+                self.turn_ended = false;
+                self.turn += 1;
+                return;
+            }
+
+            for actor in self.actors.values_mut() {
+                actor.energy += actor.speed;
+            }
+        }
+    }
+}
+
+impl Default for TurnScheduler {
+    fn default() -> Self {
+        TurnScheduler::new(false)
+    }
+}
+
+pub struct TurnSystem;
+
+impl TurnSystem {
+    pub fn new() -> Self {
+        TurnSystem
+    }
+}
+
+impl<'a> System<'a> for TurnSystem {
+    type SystemData = (Entities<'a>, ReadStorage<'a, Initiative>, ReadStorage<'a, AiComponent>, ReadStorage<'a, Position>, FetchMut<'a, TurnScheduler>);
+
+    fn run(&mut self, (entities, initiatives, ais, positions, mut scheduler): Self::SystemData) {
+        use specs::Join;
+
+        if !scheduler.enabled {
+            return;
+        }
+
+        scheduler.actors.retain(|entity, _| entities.is_alive(*entity) && initiatives.get(*entity).is_some());
+
+        for (entity, initiative) in (&*entities, &initiatives).join() {
+            let actor = scheduler.actors.entry(entity).or_insert(Actor {
+                energy: 0,
+                speed: initiative.speed,
+                waits_for_input: initiative.waits_for_input,
+            });
+            actor.speed = initiative.speed;
+            actor.waits_for_input = initiative.waits_for_input;
+        }
+
+        let current_gone = scheduler.current
+            .map(|current| !scheduler.actors.contains_key(&current))
+            .unwrap_or(true);
+
+        if scheduler.turn_ended || current_gone {
+            scheduler.next_turn();
+        }
+
+        // nothing would end the turn of an entity that doesn't wait for input and isn't run by `AiSystem`,
+        // if none of the actors can act they're tried again next tick
+        let mut skips = scheduler.actors.len();
+        while let Some(current) = scheduler.current {
+            let waits_for_input = scheduler.actors.get(&current)
+                .map(|actor| actor.waits_for_input)
+                .unwrap_or(false);
+
+            if scheduler.turn_ended || waits_for_input || (ais.get(current).is_some() && positions.get(current).is_some()) {
+                break;
+            }
+
+            scheduler.turn_ended = true;
+
+            if skips == 0 {
+                break;
+            }
+            skips -= 1;
+            scheduler.next_turn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use cgmath::Vector3;
+    use specs::{ RunNow, World };
+    use crate::{ CollisionLayers, EventBus, InitialPosition, MapMessage, MapSystem, MapTopology, Placement, ReplayHandle, Shard };
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new();
+        world.register::<AiComponent>();
+        world.register::<CollisionLayers>();
+        world.register::<InitialPosition>();
+        world.register::<Initiative>();
+        world.register::<Placement>();
+        world.register::<Position>();
+        world.add_resource(TurnScheduler::new(true));
+        world.add_resource(ReplayHandle::new());
+        world.add_resource(EventBus::default());
+        world
+    }
+
+    fn current(world: &World) -> Option<Entity> {
+        world.read_resource::<TurnScheduler>().current()
+    }
+
+    #[test]
+    fn faster_entities_get_more_turns() {
+        let mut world = world();
+        let fast = world.create_entity().with(Initiative::player(100)).build();
+        let slow = world.create_entity().with(Initiative::player(50)).build();
+
+        let mut turns = vec![];
+        for _ in 0..6 {
+            TurnSystem::new().run_now(&world.res);
+            let entity = current(&world).unwrap();
+            turns.push(entity);
+            world.write_resource::<TurnScheduler>().end_turn(entity);
+        }
+
+        assert_eq!(turns, vec![fast, fast, slow, fast, fast, slow]);
+        assert_eq!(world.read_resource::<TurnScheduler>().turn(), 6);
+    }
+
+    #[test]
+    fn nobody_goes_without_speed() {
+        let mut world = world();
+        world.create_entity().with(Initiative::player(0)).build();
+        world.create_entity().with(Initiative::new(0)).build();
+
+        TurnSystem::new().run_now(&world.res);
+
+        assert_eq!(current(&world), None);
+    }
+
+    #[test]
+    fn players_hold_the_turn_until_it_ends() {
+        let mut world = world();
+        // nothing would end its turn, so it's skipped
+        world.create_entity().with(Initiative::new(200)).build();
+        let player = world.create_entity().with(Initiative::player(100)).build();
+
+        for _ in 0..3 {
+            TurnSystem::new().run_now(&world.res);
+
+            let scheduler = world.read_resource::<TurnScheduler>();
+            assert_eq!(scheduler.current(), Some(player));
+            assert!(scheduler.waiting_for_input());
+            assert!(scheduler.is_turn(player));
+        }
+    }
+
+    #[test]
+    fn rejected_moves_end_the_turn() {
+        let mut world = world();
+        let mut map_system = MapSystem::new(Vector3::new(5, 0, 5), MapTopology::Square4);
+        let player = world.create_entity()
+            .with(Initiative::player(100))
+            .with(InitialPosition(Vector3::new(1, 0, 1)))
+            .build();
+
+        TurnSystem::new().run_now(&world.res);
+        map_system.run_now(&world.res);
+        let turn = world.read_resource::<TurnScheduler>().turn();
+
+        let (reply, moved) = mpsc::sync_channel(1);
+        map_system.sender().send(MapMessage::Move {
+            entity: player,
+            new_location: Vector3::new(-9, 0, 0),
+            absolute: false,
+            reply: Some(reply),
+        });
+        map_system.run_now(&world.res);
+
+        assert_eq!(moved.try_recv(), Ok(false));
+        assert!(!world.read_resource::<TurnScheduler>().is_turn(player));
+
+        TurnSystem::new().run_now(&world.res);
+
+        let scheduler = world.read_resource::<TurnScheduler>();
+        assert_eq!(scheduler.turn(), turn + 1);
+        assert!(scheduler.waiting_for_input());
+    }
+}
@@ -0,0 +1,58 @@
+extern crate cgmath;
+extern crate opalite;
+extern crate specs;
+
+use cgmath::Vector3;
+use opalite::{ HeadlessOpal, InitialPosition, Map, MapMessage, MessageSender, OpalBuilder, Position, RLock, Time };
+
+fn headless<'a, 'b>() -> HeadlessOpal<'a, 'b> {
+    OpalBuilder::new()
+        .add_dispatcher_start()
+        .add_dispatcher_end()
+        .add_headless()
+        .add_world()
+        .build()
+        .unwrap()
+}
+
+fn location(opal: &HeadlessOpal, entity: specs::Entity) -> Option<Vector3<i32>> {
+    let map = opal.world().read_resource::<RLock<Map>>();
+    let map = map.read().unwrap();
+    map.location(&entity).cloned()
+}
+
+#[test]
+fn step_advances_time() {
+    let mut opal = headless();
+
+    opal.step(3);
+
+    let time = opal.world().read_resource::<Time>();
+    assert_eq!(time.tick(), 3);
+    assert_eq!(time.frame(), 3);
+}
+
+#[test]
+fn step_places_and_moves_entities() {
+    let mut opal = headless();
+
+    let entity = opal.world_mut().create_entity()
+        .with(InitialPosition(Vector3::new(1, 0, 1)))
+        .build();
+
+    opal.step(1);
+
+    assert_eq!(location(&opal, entity), Some(Vector3::new(1, 0, 1)));
+    assert!(opal.world().read::<Position>().get(entity).is_some());
+
+    opal.world().write_resource::<MessageSender<MapMessage>>().send(MapMessage::Move {
+        entity,
+        new_location: Vector3::new(1, 0, 0),
+        absolute: false,
+        reply: None,
+    });
+
+    opal.step(1);
+
+    assert_eq!(location(&opal, entity), Some(Vector3::new(2, 0, 1)));
+}
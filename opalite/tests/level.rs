@@ -0,0 +1,92 @@
+extern crate cgmath;
+extern crate opalite;
+extern crate specs;
+
+use cgmath::Vector3;
+use opalite::{
+    load_level,
+    HeadlessOpal,
+    InitialPosition,
+    LevelDesc,
+    LevelEntity,
+    Map,
+    OpalBuilder,
+    PrefabDesc,
+    PrefabLibrary,
+    RLock,
+};
+
+fn headless<'a, 'b>() -> HeadlessOpal<'a, 'b> {
+    OpalBuilder::new()
+        .add_dispatcher_start()
+        .add_dispatcher_end()
+        .add_headless()
+        .add_world()
+        .build()
+        .unwrap()
+}
+
+fn location(opal: &HeadlessOpal, entity: specs::Entity) -> Option<Vector3<i32>> {
+    let map = opal.world().read_resource::<RLock<Map>>();
+    let map = map.read().unwrap();
+    map.location(&entity).cloned()
+}
+
+fn is_alive(opal: &HeadlessOpal, entity: specs::Entity) -> bool {
+    opal.world().entities().is_alive(entity)
+}
+
+fn level(prefabs: &[&str]) -> LevelDesc {
+    LevelDesc {
+        map_dimensions: (10, 10, 10),
+        map_topology: None,
+        terrain: None,
+        camera: None,
+        lights: vec![],
+        entities: prefabs.iter()
+            .enumerate()
+            .map(|(i, prefab)| LevelEntity {
+                prefab: prefab.to_string(),
+                position: (i as i32, 0, 2),
+                overrides: PrefabDesc::default(),
+            })
+            .collect(),
+    }
+}
+
+fn spawn_existing(opal: &mut HeadlessOpal) -> specs::Entity {
+    let entity = opal.world_mut().create_entity()
+        .with(InitialPosition(Vector3::new(1, 0, 1)))
+        .build();
+    opal.step(1);
+    entity
+}
+
+#[test]
+fn levels_replace_the_entities_on_the_map() {
+    let mut opal = headless();
+    opal.world().write_resource::<PrefabLibrary>().insert("crate", PrefabDesc::default());
+    let existing = spawn_existing(&mut opal);
+
+    let created = load_level(opal.world_mut(), level(&["crate", "crate"])).unwrap();
+    opal.step(1);
+
+    assert!(!is_alive(&opal, existing));
+    assert_eq!(created.len(), 2);
+    assert_eq!(location(&opal, created[0]), Some(Vector3::new(0, 0, 2)));
+    assert_eq!(location(&opal, created[1]), Some(Vector3::new(1, 0, 2)));
+}
+
+#[test]
+fn unknown_prefabs_leave_the_world_alone() {
+    let mut opal = headless();
+    opal.world().write_resource::<PrefabLibrary>().insert("crate", PrefabDesc::default());
+    let existing = spawn_existing(&mut opal);
+
+    assert!(load_level(opal.world_mut(), level(&["crate", "missing"])).is_err());
+    opal.step(1);
+
+    assert!(is_alive(&opal, existing));
+    assert_eq!(location(&opal, existing), Some(Vector3::new(1, 0, 1)));
+    assert_eq!(opal.world().read_resource::<RLock<Map>>().read().unwrap().len(), 1);
+}
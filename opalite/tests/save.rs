@@ -0,0 +1,87 @@
+extern crate cgmath;
+extern crate opalite;
+extern crate specs;
+
+use cgmath::Vector3;
+use opalite::{ Data, HeadlessOpal, InitialPosition, Initiative, Map, OpalBuilder, RLock, SaveRegistry };
+
+fn headless<'a, 'b>() -> HeadlessOpal<'a, 'b> {
+    OpalBuilder::new()
+        .add_dispatcher_start()
+        .add_dispatcher_end()
+        .add_headless()
+        .add_world()
+        .build()
+        .unwrap()
+}
+
+fn location(opal: &HeadlessOpal, entity: specs::Entity) -> Option<Vector3<i32>> {
+    let map = opal.world().read_resource::<RLock<Map>>();
+    let map = map.read().unwrap();
+    map.location(&entity).cloned()
+}
+
+fn registry(opal: &HeadlessOpal) -> SaveRegistry {
+    opal.world().read_resource::<SaveRegistry>().clone()
+}
+
+fn is_alive(opal: &HeadlessOpal, entity: specs::Entity) -> bool {
+    opal.world().entities().is_alive(entity)
+}
+
+fn spawn_goblin(opal: &mut HeadlessOpal) -> specs::Entity {
+    let data = Data::new();
+    data.insert("goblin".to_owned());
+
+    opal.world_mut().create_entity()
+        .with(InitialPosition(Vector3::new(1, 0, 1)))
+        .with(Initiative::new(50))
+        .with(data)
+        .build()
+}
+
+#[test]
+fn saved_entities_are_loaded_back() {
+    let mut opal = headless();
+    opal.save_registry_mut().register_data::<String>("Name");
+
+    let goblin = spawn_goblin(&mut opal);
+    let unsaved = opal.world_mut().create_entity().build();
+    opal.step(1);
+
+    let registry = registry(&opal);
+    let file = registry.save(opal.world()).unwrap();
+    assert_eq!(file.entities.len(), 1);
+
+    let loaded = registry.load(opal.world_mut(), file).unwrap();
+    opal.step(1);
+
+    assert_eq!(loaded.len(), 1);
+    assert!(!is_alive(&opal, goblin));
+    assert!(is_alive(&opal, unsaved));
+
+    let entity = loaded[0];
+    assert_eq!(location(&opal, entity), Some(Vector3::new(1, 0, 1)));
+    assert_eq!(opal.world().read::<Initiative>().get(entity), Some(&Initiative::new(50)));
+
+    let datas = opal.world().read::<Data>();
+    assert_eq!(datas.get(entity).and_then(|data| data.get::<String>()), Some("goblin".to_owned()));
+}
+
+#[test]
+fn corrupt_saves_leave_the_world_alone() {
+    let mut opal = headless();
+    let goblin = spawn_goblin(&mut opal);
+    opal.step(1);
+
+    let registry = registry(&opal);
+    let mut file = registry.save(opal.world()).unwrap();
+    file.entities[0].components.insert("Initiative".to_owned(), "not ron".to_owned());
+
+    assert!(registry.load(opal.world_mut(), file).is_err());
+    opal.step(1);
+
+    assert!(is_alive(&opal, goblin));
+    assert_eq!(location(&opal, goblin), Some(Vector3::new(1, 0, 1)));
+    assert_eq!(opal.world().read::<Initiative>().get(goblin), Some(&Initiative::new(50)));
+}